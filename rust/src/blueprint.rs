@@ -0,0 +1,437 @@
+use std::io::{self, Write};
+
+use glam::{Vec2, Vec3};
+
+use crate::types::Mesh;
+use crate::palette::Palette;
+use crate::export::Exporter;
+use crate::view::View;
+
+/// Pixels for the largest dimension of the model
+const MODEL_SIZE: f32 = 400f32;
+/// Room around each view for dimension lines and its caption
+const MARGIN: f32 = 64f32;
+const SCALE_BAR_HEIGHT: f32 = 48f32;
+
+const DIMENSION_STYLE: &str = "stroke=\"#0050a0\" stroke-width=\"1\"";
+const TEXT_STYLE: &str = "\
+    font-family=\"sans-serif\" font-size=\"12\" fill=\"#0050a0\"";
+
+/// Orthographic drawings of the mesh side by side, all at the same scale,
+/// with its overall dimensions and a scale bar.  The model faces along Y
+/// and stands on its lowest point.
+pub struct Blueprint<'a> {
+    pub mesh: &'a Mesh,
+    pub palette: &'a Palette,
+    pub views: &'a [View],
+    /// Fill the outline only, rather than drawing the faces
+    pub silhouette: bool,
+    /// Top of the withers above the lowest point, to mark on side views
+    pub withers: Option<f32>,
+}
+
+struct Bounds {
+    mins: Vec3,
+    maxs: Vec3,
+}
+
+impl Bounds {
+    fn size(&self) -> Vec3 {
+        self.maxs - self.mins
+    }
+}
+
+impl Blueprint<'_> {
+    fn bounds(&self) -> Bounds {
+        let (mins, maxs) = self.mesh.bounds().unwrap_or_default();
+        Bounds { mins, maxs }
+    }
+
+    /// Draws one view with its top left corner at `origin`, returning its
+    /// width on the page
+    fn write_view(
+        &self,
+        writer: &mut dyn Write,
+        view: View,
+        origin: Vec2,
+        bounds: &Bounds,
+        scale: f32,
+    ) -> io::Result<f32> {
+        let corners = [bounds.mins, bounds.maxs].map(|v| view.project(v));
+        let mins = corners[0].min(corners[1]);
+        let maxs = corners[0].max(corners[1]);
+
+        // Page position of a point in the view, with Y running down
+        let to_page = |p: Vec2| Vec2::new(
+            origin.x + MARGIN + (p.x - mins.x) * scale,
+            origin.y + MARGIN + (maxs.y - p.y) * scale,
+        );
+
+        writeln!(writer, "  <g id=\"{}\">", view.name())?;
+        writeln!(
+            writer,
+            "    <text x=\"{:.2}\" y=\"{:.2}\" {TEXT_STYLE} \
+             font-weight=\"bold\">{} view</text>",
+            origin.x + MARGIN,
+            origin.y + MARGIN / 2f32,
+            capitalize(view.name()),
+        )?;
+
+        self.write_faces(writer, view, &to_page)?;
+
+        // Overall dimensions: across below the drawing, up along its left
+        let size = bounds.size();
+        let [across, up] = match view {
+            View::Front => [("Width", size.x), ("Height", size.z)],
+            View::Side => [("Length", size.y), ("Height", size.z)],
+            View::Top => [("Width", size.x), ("Length", size.y)],
+        };
+
+        let bottom_left = to_page(mins);
+        let bottom_right = to_page(Vec2::new(maxs.x, mins.y));
+        let top_left = to_page(Vec2::new(mins.x, maxs.y));
+        let below = Vec2::new(0f32, MARGIN / 3f32);
+        let left = Vec2::new(-MARGIN / 3f32, 0f32);
+
+        write_dimension(
+            writer,
+            bottom_left + below,
+            bottom_right + below,
+            &format!("{} {:.2}", across.0, across.1),
+        )?;
+        write_dimension(
+            writer,
+            bottom_left + left,
+            top_left + left,
+            &format!("{} {:.2}", up.0, up.1),
+        )?;
+
+        if view == View::Side
+            && let Some(withers) = self.withers
+        {
+            let right = Vec2::new(MARGIN / 3f32, 0f32);
+            let top = to_page(Vec2::new(maxs.x, mins.y + withers));
+
+            write_dimension(
+                writer,
+                bottom_right + right,
+                top + right,
+                &format!("Height at withers {withers:.2}"),
+            )?;
+        }
+
+        writeln!(writer, "  </g>")?;
+
+        Ok((maxs.x - mins.x) * scale + 2f32 * MARGIN)
+    }
+
+    /// Faces towards the viewer, painted back to front
+    fn write_faces(
+        &self,
+        writer: &mut dyn Write,
+        view: View,
+        to_page: &dyn Fn(Vec2) -> Vec2,
+    ) -> io::Result<()> {
+        let toward = view.toward_viewer();
+        let verts = self.mesh.verts();
+
+        let mut faces: Vec<_> = self.mesh.faces()
+            .filter_map(|(face, meta)| {
+                let normal = self.mesh.face_normal(face).normalize_or_zero();
+                let facing = normal.dot(toward);
+
+                if facing <= 0f32 {
+                    return None;
+                }
+
+                let depth = face.iter()
+                    .map(|&idx| verts[idx].dot(toward))
+                    .sum::<f32>() / face.len() as f32;
+
+                Some((depth, facing, face, meta))
+            })
+            .collect();
+
+        faces.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        if self.silhouette {
+            writeln!(
+                writer,
+                "    <g fill=\"#404040\" stroke=\"#404040\" \
+                 stroke-width=\"0.5\" stroke-linejoin=\"round\">",
+            )?;
+        } else {
+            writeln!(
+                writer,
+                "    <g stroke=\"#202020\" stroke-width=\"0.3\" \
+                 stroke-linejoin=\"round\">",
+            )?;
+        }
+
+        for (_, facing, face, meta) in faces {
+            let points: Vec<_> = face.iter()
+                .map(|&idx| {
+                    let p = to_page(view.project(verts[idx]));
+                    format!("{:.2},{:.2}", p.x, p.y)
+                })
+                .collect();
+
+            if self.silhouette {
+                writeln!(
+                    writer,
+                    "      <polygon points=\"{}\"/>",
+                    points.join(" "),
+                )?;
+            } else {
+                // Shade by how squarely the face is seen
+                let color = self.palette.color(meta.and_then(|m| m.color))
+                    * (0.4f32 + 0.6f32 * facing);
+                let [r, g, b] = (color * 255f32).round().as_uvec3().into();
+
+                writeln!(
+                    writer,
+                    "      <polygon points=\"{}\" \
+                     fill=\"#{r:02x}{g:02x}{b:02x}\"/>",
+                    points.join(" "),
+                )?;
+            }
+        }
+
+        writeln!(writer, "    </g>")?;
+
+        Ok(())
+    }
+}
+
+impl Exporter for Blueprint<'_> {
+    fn export(&self, writer: &mut dyn Write) -> io::Result<()> {
+        let bounds = self.bounds();
+        let scale = MODEL_SIZE / bounds.size().max_element().max(f32::EPSILON);
+
+        let widths: Vec<_> = self.views.iter()
+            .map(|view| {
+                let corners = [bounds.mins, bounds.maxs]
+                    .map(|v| view.project(v));
+                (corners[0] - corners[1]).abs() * scale
+                    + Vec2::splat(2f32 * MARGIN)
+            })
+            .collect();
+
+        let page = Vec2::new(
+            widths.iter().map(|size| size.x).sum::<f32>(),
+            widths.iter().map(|size| size.y).fold(0f32, f32::max)
+                + SCALE_BAR_HEIGHT,
+        ).max(Vec2::new(MARGIN * 4f32, SCALE_BAR_HEIGHT));
+
+        writeln!(
+            writer,
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" \
+             width=\"{:.0}\" height=\"{:.0}\" viewBox=\"0 0 {:.2} {:.2}\">",
+            page.x.ceil(), page.y.ceil(), page.x, page.y,
+        )?;
+        writeln!(writer, "  <title>Blueprint</title>")?;
+        writeln!(
+            writer,
+            "  <rect width=\"100%\" height=\"100%\" fill=\"white\"/>",
+        )?;
+
+        let mut x = 0f32;
+
+        for &view in self.views {
+            x+= self.write_view(
+                writer,
+                view,
+                Vec2::new(x, 0f32),
+                &bounds,
+                scale,
+            )?;
+        }
+
+        // Round length covering about a quarter of the model
+        let target = bounds.size().max_element() / 4f32;
+        let length = nice_length(target);
+        let start = Vec2::new(MARGIN, page.y - SCALE_BAR_HEIGHT / 2f32);
+        let end = start + Vec2::new(length * scale, 0f32);
+        let tick = Vec2::new(0f32, 4f32);
+
+        writeln!(writer, "  <g id=\"scale\" {DIMENSION_STYLE}>")?;
+        write_line(writer, start, end)?;
+        write_line(writer, start - tick, start + tick)?;
+        write_line(writer, end - tick, end + tick)?;
+        writeln!(writer, "  </g>")?;
+        writeln!(
+            writer,
+            "  <text x=\"{:.2}\" y=\"{:.2}\" {TEXT_STYLE}>{} units</text>",
+            end.x + 8f32,
+            end.y + 4f32,
+            format_length(length),
+        )?;
+
+        writeln!(writer, "</svg>")?;
+
+        Ok(())
+    }
+}
+
+/// Dimension line between two page points with arrowheads and a label
+/// alongside
+fn write_dimension(
+    writer: &mut dyn Write,
+    start: Vec2,
+    end: Vec2,
+    label: &str,
+) -> io::Result<()> {
+    let along = (end - start).normalize_or_zero();
+    let side = along.perp() * 3f32;
+    let head = along * 6f32;
+
+    writeln!(writer, "    <g {DIMENSION_STYLE} fill=\"#0050a0\">")?;
+    write_line(writer, start, end)?;
+
+    for (tip, back) in [(start, head), (end, -head)] {
+        let [a, b] = [tip + back + side, tip + back - side];
+        writeln!(
+            writer,
+            "      <polygon points=\"{:.2},{:.2} {:.2},{:.2} {:.2},{:.2}\"/>",
+            tip.x, tip.y, a.x, a.y, b.x, b.y,
+        )?;
+    }
+
+    writeln!(writer, "    </g>")?;
+
+    let middle = (start + end) / 2f32;
+
+    // Vertical dimensions read bottom to top
+    if along.x.abs() >= along.y.abs() {
+        writeln!(
+            writer,
+            "    <text x=\"{:.2}\" y=\"{:.2}\" {TEXT_STYLE} \
+             text-anchor=\"middle\">{label}</text>",
+            middle.x,
+            middle.y + 16f32,
+        )?;
+    } else {
+        writeln!(
+            writer,
+            "    <text x=\"{0:.2}\" y=\"{1:.2}\" {TEXT_STYLE} \
+             text-anchor=\"middle\" \
+             transform=\"rotate(-90 {0:.2} {1:.2})\">{label}</text>",
+            middle.x - 6f32,
+            middle.y,
+        )?;
+    }
+
+    Ok(())
+}
+
+fn write_line(
+    writer: &mut dyn Write,
+    start: Vec2,
+    end: Vec2,
+) -> io::Result<()> {
+    writeln!(
+        writer,
+        "      <line x1=\"{:.2}\" y1=\"{:.2}\" x2=\"{:.2}\" y2=\"{:.2}\"/>",
+        start.x, start.y, end.x, end.y,
+    )
+}
+
+/// Largest 1, 2 or 5 times a power of ten not above `target`
+fn nice_length(target: f32) -> f32 {
+    if target <= 0f32 || !target.is_finite() {
+        return 1f32;
+    }
+
+    let power = 10f32.powf(target.log10().floor());
+
+    [5f32, 2f32, 1f32].into_iter()
+        .map(|step| step * power)
+        .find(|&length| length <= target)
+        .unwrap_or(power)
+}
+
+fn format_length(length: f32) -> String {
+    if length >= 1f32 {
+        format!("{length:.0}")
+    } else {
+        format!("{length}")
+    }
+}
+
+fn capitalize(name: &str) -> String {
+    let mut chars = name.chars();
+
+    chars.next()
+        .map(|first| first.to_uppercase().chain(chars).collect())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dimensions() {
+        // Box 2 wide, 4 long and 1 high
+        let mut mesh = Mesh::default();
+        let corners: Vec<_> = (0..8)
+            .map(|idx| Vec3::new(
+                if idx & 1 == 0 { -1f32 } else { 1f32 },
+                if idx & 2 == 0 { 0f32 } else { 4f32 },
+                if idx & 4 == 0 { 0f32 } else { 1f32 },
+            ))
+            .collect();
+        let verts = mesh.add_ring(&corners);
+
+        for face in [
+            [0, 2, 3, 1], [4, 5, 7, 6], [0, 1, 5, 4],
+            [2, 6, 7, 3], [0, 4, 6, 2], [1, 3, 7, 5],
+        ] {
+            mesh.add_face(face.map(|c| verts[c]).into(), vec![], None);
+        }
+
+        let mut out = Vec::new();
+        Blueprint {
+            mesh: &mesh,
+            palette: &Palette::default(),
+            views: &[View::Front, View::Side, View::Top],
+            silhouette: false,
+            withers: Some(0.75f32),
+        }.export(&mut out).unwrap();
+
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<_> = out.lines().collect();
+
+        fn label(line: &str) -> Option<&str> {
+            let text = line.strip_suffix("</text>")?;
+            Some(&text[text.rfind('>')? + 1..])
+        }
+
+        let labels: Vec<_> = lines.iter().filter_map(|l| label(l)).collect();
+
+        assert_eq!(labels, [
+            "Front view", "Width 2.00", "Height 1.00",
+            "Side view", "Length 4.00", "Height 1.00",
+            "Height at withers 0.75",
+            "Top view", "Width 2.00", "Length 4.00",
+            "1 units",
+        ]);
+
+        // The withers line rises from the ground to its height, at the
+        // 100 pixels per unit that fit the 4 unit length in 400
+        let withers = lines.iter()
+            .position(|l| label(l) == Some("Height at withers 0.75"))
+            .unwrap();
+        let line = lines[..withers].iter()
+            .rfind(|l| l.trim_start().starts_with("<line"))
+            .unwrap();
+        let coords: Vec<f32> = line.split('"')
+            .skip(1)
+            .step_by(2)
+            .map(|value| value.parse().unwrap())
+            .collect();
+
+        assert_eq!(coords[0], coords[2]);
+        assert!((coords[1] - coords[3] - 75f32).abs() < 0.01f32);
+    }
+}
@@ -0,0 +1,1014 @@
+use std::rc::Rc;
+use std::cell::OnceCell;
+use std::f32::consts::{FRAC_PI_2, TAU};
+
+use glam::{Affine3, Quat, Vec2, Vec3};
+
+use crate::types::{
+    cooked::BodySegment, Disk, Mesh, MeshMeta, Triangulation, VertexSource,
+};
+use crate::union::{boolean_union, voxel_union, UnionReport};
+use crate::sink::{walk_body, Branch, MeshSink, RingEvent};
+use crate::select::Selection;
+
+const DEFAULT_DOME_RINGS: usize = 3;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum CapStyle {
+    /// Leave chain ends open
+    #[default]
+    None,
+    /// Single polygon over the end disk
+    Flat,
+    /// Triangles meeting at the end disk's centroid
+    Fan,
+    /// Rounded cap with the given number of rings before the apex
+    Dome(usize),
+}
+
+impl CapStyle {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "none" => Some(Self::None),
+            "flat" => Some(Self::Flat),
+            "fan" => Some(Self::Fan),
+            "dome" => Some(Self::Dome(DEFAULT_DOME_RINGS)),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum JunctionStyle {
+    /// Start every branch from the whole parent ring, overlapping
+    #[default]
+    Overlap,
+    /// Split the parent ring between the branches, so they join it in a
+    /// single surface
+    Stitch,
+}
+
+impl JunctionStyle {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "overlap" => Some(Self::Overlap),
+            "stitch" => Some(Self::Stitch),
+            _ => None,
+        }
+    }
+}
+
+/// How the tubes are merged into one shell
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UnionStyle {
+    /// Cut faces where tubes cross and drop the parts inside other tubes
+    Boolean,
+    /// Remesh the whole shell from a grid with this many cells along the
+    /// longest side
+    Voxel(usize),
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct BuildOptions {
+    /// Merge vertices closer than this after building
+    pub weld: Option<f32>,
+    /// How to close the disks at the root and leaves of the body tree
+    pub cap: CapStyle,
+    /// How branches leave the ring they share
+    pub junction: JunctionStyle,
+    /// Split faces into triangles after building
+    pub triangulate: Option<Triangulation>,
+    /// Extra rings interpolated between each pair of consecutive disks
+    pub smooth_rings: usize,
+    /// Merge the tubes into a single shell
+    pub union: Option<UnionStyle>,
+    /// Levels of Catmull-Clark subdivision applied after welding
+    pub subdivide: usize,
+    /// Part of the body tree to build
+    pub selection: Selection,
+}
+
+/// Builds the mesh, along with what went wrong merging the tubes if they
+/// were merged
+pub fn build_mesh(
+    body: Option<&BodySegment>,
+    options: &BuildOptions,
+) -> (Mesh, Option<UnionReport>) {
+    let mut builder = MeshBuilder::new(options);
+    walk_body(body, &options.selection, &mut builder);
+    let mut mesh = builder.mesh;
+
+    mesh.pack_uvs();
+
+    if let Some(tolerance) = options.weld {
+        mesh.weld(tolerance);
+    }
+
+    let mut union_report = None;
+
+    if let Some(style) = options.union {
+        let (shell, report) = match style {
+            UnionStyle::Boolean => boolean_union(&mesh),
+            UnionStyle::Voxel(resolution) => voxel_union(&mesh, resolution),
+        };
+
+        if report.faces > 0 {
+            mesh = shell;
+        }
+
+        union_report = Some(report);
+    }
+
+    mesh.subdivide(options.subdivide);
+
+    if let Some(mode) = options.triangulate {
+        mesh.triangulate(mode);
+    }
+
+    (mesh, union_report)
+}
+
+/// Transformed disk, added to the mesh once a band first needs it
+struct Ring {
+    /// Segment frame the disk sits in
+    frame: Affine3,
+    /// Disk vertices, scaled, within the frame
+    local: Vec<Vec3>,
+    positions: Vec<Vec3>,
+    /// Which positions came from crease vertices of the disk
+    creases: Vec<bool>,
+    /// Disk vertex each position was placed from, if any
+    sources: Vec<Option<VertexSource>>,
+    /// Angular position of each disk vertex in turns, unwrapped so that it
+    /// changes steadily around the disk, and repeating the first vertex
+    /// one turn on at the end
+    turns: Vec<f32>,
+    /// Distance along the chain from the root
+    arc_length: f32,
+    indices: OnceCell<Vec<usize>>,
+    /// Metadata for any cap closing this ring
+    meta: MeshMeta,
+    /// Whether the ring starts the tree rather than continuing a chain
+    root: bool,
+}
+
+impl Ring {
+    fn indices(&self, mesh: &mut Mesh) -> &[usize] {
+        self.indices.get_or_init(|| {
+            let indices = mesh.add_ring(&self.positions[..]);
+
+            for (&idx, _) in indices.iter()
+                .zip(&self.creases)
+                .filter(|&(_, &crease)| crease)
+            {
+                mesh.mark_crease(idx);
+            }
+
+            for (&idx, source) in indices.iter().zip(&self.sources) {
+                if let Some(source) = source {
+                    mesh.set_source(idx, *source);
+                }
+            }
+
+            indices
+        })
+    }
+
+    /// Ring part way to `end`, following a Hermite curve leaving and
+    /// entering along the frames' Z axes, with the frame's orientation and
+    /// the disk's shape blended between them
+    fn between(&self, end: &Ring, t: f32) -> Ring {
+        let start_pos = self.frame.translation;
+        let end_pos = end.frame.translation;
+        let offset = end_pos - start_pos;
+        let start_dir = self.frame.matrix3.z_axis;
+        let end_dir = end.frame.matrix3.z_axis;
+
+        // Negative shifts run the chain backwards along the frames
+        let speed = offset.length().copysign(offset.dot(end_dir));
+        let start_tangent = start_dir * speed;
+        let end_tangent = end_dir * speed;
+
+        let (t2, t3) = (t * t, t * t * t);
+        let position = start_pos * (2f32 * t3 - 3f32 * t2 + 1f32)
+            + start_tangent * (t3 - 2f32 * t2 + t)
+            + end_pos * (3f32 * t2 - 2f32 * t3)
+            + end_tangent * (t3 - t2);
+
+        let rotation = Quat::from_mat3(&self.frame.matrix3)
+            .slerp(Quat::from_mat3(&end.frame.matrix3), t);
+        let frame = Affine3::from_rotation_translation(rotation, position);
+
+        let local: Vec<_> = self.local.iter()
+            .zip(&end.local)
+            .map(|(a, b)| a.lerp(*b, t))
+            .collect();
+
+        Ring {
+            frame,
+            positions: local.iter()
+                .map(|&v| frame.transform_point3(v))
+                .collect(),
+            sources: vec![None; local.len()],
+            local,
+            creases: end.creases.clone(),
+            turns: self.turns.iter()
+                .zip(&end.turns)
+                .map(|(a, b)| a + (b - a) * t)
+                .collect(),
+            arc_length: 0f32,
+            indices: OnceCell::new(),
+            meta: end.meta.clone(),
+            root: false,
+        }
+    }
+
+    fn centroid(&self) -> Vec3 {
+        self.positions.iter().sum::<Vec3>() / self.positions.len() as f32
+    }
+
+    fn perimeter(&self) -> f32 {
+        (0..self.positions.len())
+            .map(|idx| {
+                let next_idx = (idx + 1) % self.positions.len();
+                self.positions[idx].distance(self.positions[next_idx])
+            })
+            .sum()
+    }
+
+    /// Adds texture coordinates for the ring, with U spanning `width` per
+    /// turn and V running along the chain
+    fn add_uvs(&self, mesh: &mut Mesh, width: f32) -> Vec<usize> {
+        let uvs: Vec<_> = self.turns.iter()
+            .map(|&turn| Vec2::new(turn * width, self.arc_length))
+            .collect();
+
+        mesh.add_uvs(&uvs[..])
+    }
+}
+
+/// State carried down a chain from one ring to the next
+#[derive(Clone, Default)]
+struct Chain {
+    ring: Option<Rc<Ring>>,
+    /// Texture coordinates of `ring` in the chain's island, if a band has
+    /// already been laid out there.  Branches leave this empty so that they
+    /// start islands of their own.
+    uvs: Option<Rc<Vec<usize>>>,
+    /// U span of a full turn within the island
+    uv_width: f32,
+}
+
+/// Segment being walked
+#[derive(Default)]
+struct Scope {
+    chain: Chain,
+    /// Chains for the left and right branches, taken in turn as they are
+    /// entered
+    branches: Vec<Chain>,
+    /// Ring placed by the segment
+    ring: Option<Rc<Ring>>,
+    /// Whether the ring ends a chain
+    leaf: bool,
+}
+
+/// Builds tubes, joining each ring to the one before it on its chain.
+/// Both branches of a segment continue from the same ring, so chains share
+/// vertices where they meet.
+struct MeshBuilder<'a> {
+    mesh: Mesh,
+    options: &'a BuildOptions,
+    scopes: Vec<Scope>,
+}
+
+impl<'a> MeshBuilder<'a> {
+    fn new(options: &'a BuildOptions) -> Self {
+        Self {
+            mesh: Mesh::default(),
+            options,
+            scopes: Vec::new(),
+        }
+    }
+}
+
+impl MeshSink for MeshBuilder<'_> {
+    fn enter_segment(&mut self, _segment: &BodySegment, _xform: Affine3) {
+        let chain = match self.scopes.last_mut() {
+            Some(parent) if !parent.branches.is_empty() => {
+                parent.branches.remove(0)
+            },
+            Some(parent) => parent.chain.clone(),
+            None => Chain::default(),
+        };
+
+        self.scopes.push(Scope { chain, ..Scope::default() });
+    }
+
+    fn ring(&mut self, event: &RingEvent) {
+        let Some(scope) = self.scopes.last_mut() else {
+            return;
+        };
+
+        let mesh = &mut self.mesh;
+        let chain = &mut scope.chain;
+        let (local, creases): (Vec<_>, _) = event.local().unzip();
+
+        let mut ring = Ring {
+            frame: event.frame,
+            positions: local.iter()
+                .map(|&v| event.frame.transform_point3(v))
+                .collect(),
+            local,
+            creases,
+            sources: event.sources().map(Some).collect(),
+            turns: turns(event.disk),
+            arc_length: 0f32,
+            indices: OnceCell::new(),
+            meta: event.meta.clone(),
+            root: chain.ring.is_none(),
+        };
+
+        if let Some(old_ring) = chain.ring.clone() {
+            let mut meta = Some(event.meta.clone());
+            let mut start_ring = old_ring.clone();
+
+            // Disks of different sizes can't be blended, so they are
+            // joined directly
+            let steps = if old_ring.local.len() == ring.local.len() {
+                self.options.smooth_rings
+            } else {
+                0
+            };
+
+            for step in 1..=steps {
+                let t = step as f32 / (steps + 1) as f32;
+                let mut mid_ring = old_ring.between(&ring, t);
+                mid_ring.arc_length = start_ring.arc_length
+                    + start_ring.centroid().distance(mid_ring.centroid());
+
+                let mid_ring = Rc::new(mid_ring);
+                add_band(mesh, chain, &start_ring, &mid_ring, &mut meta);
+                start_ring = mid_ring;
+            }
+
+            ring.arc_length = start_ring.arc_length
+                + start_ring.centroid().distance(ring.centroid());
+            add_band(mesh, chain, &start_ring, &ring, &mut meta);
+        }
+
+        let ring = Rc::new(ring);
+        scope.ring = Some(Rc::clone(&ring));
+        chain.ring = Some(ring);
+    }
+
+    fn branch(&mut self, branch: &Branch) {
+        let Some(scope) = self.scopes.last_mut() else {
+            return;
+        };
+
+        let mut left_chain = Chain { uvs: None, ..scope.chain.clone() };
+        let mut right_chain = left_chain.clone();
+
+        if self.options.junction == JunctionStyle::Stitch
+            && let Some(ring) = scope.chain.ring.as_ref()
+            && let [Some(left_ring), Some(right_ring)] = branch.first_rings()
+            && let Some([left_loop, right_loop]) = split_ring(
+                &mut self.mesh,
+                ring,
+                [&left_ring[..], &right_ring[..]],
+            )
+        {
+            left_chain.ring = Some(Rc::new(left_loop));
+            right_chain.ring = Some(Rc::new(right_loop));
+        }
+
+        scope.branches = vec![left_chain, right_chain];
+    }
+
+    fn gap(&mut self) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.chain = Chain::default();
+        }
+    }
+
+    fn leaf(&mut self) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.leaf = true;
+        }
+    }
+
+    fn exit_segment(&mut self) {
+        let Some(scope) = self.scopes.pop() else {
+            return;
+        };
+
+        if let Some(ring) = scope.ring.as_ref()
+            && let Some(indices) = ring.indices.get()
+        {
+            let meta = MeshMeta {
+                cap: true,
+                ..ring.meta.clone()
+            };
+
+            if ring.root {
+                let reversed: Vec<_> = indices.iter().rev().copied().collect();
+                add_cap(
+                    &mut self.mesh,
+                    &reversed[..],
+                    self.options.cap,
+                    meta.clone(),
+                );
+            }
+
+            if scope.leaf {
+                add_cap(&mut self.mesh, indices, self.options.cap, meta);
+            }
+        }
+    }
+}
+
+/// Splits a ring between two branches leaving it.  Each branch gets a loop
+/// of the ring vertices on its side, closed by a row of new vertices
+/// across the ring that both loops share.  Loops start wherever lines them
+/// up best with their branch's ring, to keep the bands joining them from
+/// twisting.  Returns `None` if the ring can't be split
+/// cleanly, leaving the branches to overlap.
+fn split_ring(
+    mesh: &mut Mesh,
+    ring: &Ring,
+    branches: [&[Vec3]; 2],
+) -> Option<[Ring; 2]> {
+    let indices = ring.indices(mesh).to_vec();
+    let len = indices.len();
+
+    if len < 3 || branches.iter().any(|branch| branch.is_empty()) {
+        return None;
+    }
+
+    let center = ring.centroid();
+    let normal = mesh.face_normal(&indices[..]).normalize_or_zero();
+    let [left_center, right_center] = branches.map(|branch| {
+        branch.iter().sum::<Vec3>() / branch.len() as f32
+    });
+
+    let mut across = left_center - right_center;
+    across-= normal * across.dot(normal);
+
+    let left_side: Vec<_> = ring.positions.iter()
+        .map(|&v| (v - center).dot(across) > 0f32)
+        .collect();
+
+    // Both sides must be a single run of vertices, with at least two on
+    // the right so that the split has two distinct ends
+    let starts: Vec<_> = (0..len)
+        .filter(|&idx| left_side[idx] && !left_side[(idx + len - 1) % len])
+        .collect();
+    let left_ct = left_side.iter().filter(|&&left| left).count();
+
+    let &[left_start] = &starts[..] else {
+        return None;
+    };
+
+    if len - left_ct < 2 {
+        return None;
+    }
+
+    let split_start = (left_start + len - 1) % len;
+    let split_end = (left_start + left_ct) % len;
+
+    let arc = |from: usize, to: usize| {
+        let arc_len = (to + len - from) % len + 1;
+        (0..arc_len).map(|step| indices[(from + step) % len]).collect()
+    };
+    let left_arc: Vec<usize> = arc(split_start, split_end);
+    let right_arc: Vec<usize> = arc(split_end, split_start);
+
+    let split_ct = len.saturating_sub(left_arc.len().max(right_arc.len()));
+    let (start_pos, end_pos) = (
+        mesh.verts()[indices[split_start]],
+        mesh.verts()[indices[split_end]],
+    );
+
+    // Runs from the end of the left arc back to its start
+    let split: Vec<_> = (1..=split_ct)
+        .map(|step| {
+            let t = step as f32 / (split_ct + 1) as f32;
+            mesh.add_vert(end_pos.lerp(start_pos, t))
+        })
+        .collect();
+
+    let loops = [
+        left_arc.into_iter().chain(split.iter().copied()).collect(),
+        right_arc.into_iter().chain(split.iter().rev().copied()).collect(),
+    ];
+
+    let direction = match ring.turns[..] {
+        [first, .., last] => (last - first).signum(),
+        _ => 1f32,
+    };
+    let to_local = ring.frame.inverse();
+
+    let mut loops = loops.into_iter()
+        .zip(branches)
+        .map(|(mut loop_indices, branch): (Vec<usize>, _)| {
+            let loop_len = loop_indices.len();
+            let twist = |offset: usize| {
+                (0..loop_len)
+                    .map(|step| {
+                        let idx = loop_indices[(offset + step) % loop_len];
+                        let paired = branch[step * branch.len() / loop_len];
+                        mesh.verts()[idx].distance_squared(paired)
+                    })
+                    .sum::<f32>()
+            };
+            let offset = (0..loop_len)
+                .min_by(|&a, &b| twist(a).total_cmp(&twist(b)))
+                .unwrap_or(0);
+
+            loop_indices.rotate_left(offset);
+
+            let positions: Vec<_> = loop_indices.iter()
+                .map(|&idx| mesh.verts()[idx])
+                .collect();
+
+            Ring {
+                frame: ring.frame,
+                local: positions.iter()
+                    .map(|&v| to_local.transform_point3(v))
+                    .collect(),
+                positions,
+                creases: loop_indices.iter()
+                    .map(|&idx| mesh.is_crease(idx))
+                    .collect(),
+                sources: loop_indices.iter()
+                    .map(|&idx| mesh.source(idx).copied())
+                    .collect(),
+                turns: (0..=loop_len)
+                    .map(|step| {
+                        ring.turns[0]
+                            + direction * step as f32 / loop_len as f32
+                    })
+                    .collect(),
+                arc_length: ring.arc_length,
+                indices: OnceCell::from(loop_indices),
+                meta: ring.meta.clone(),
+                root: false,
+            }
+        });
+
+    Some([loops.next()?, loops.next()?])
+}
+
+/// Connects the chain's last ring to the next one, continuing the chain's
+/// texture coordinate island or starting a new one
+fn add_band(
+    mesh: &mut Mesh,
+    chain: &mut Chain,
+    start_ring: &Ring,
+    end_ring: &Ring,
+    meta: &mut Option<MeshMeta>,
+) {
+    let start_uvs = match chain.uvs.take() {
+        Some(uvs) => uvs,
+        None => {
+            chain.uv_width = start_ring.perimeter();
+            Rc::new(start_ring.add_uvs(mesh, chain.uv_width))
+        },
+    };
+
+    let end_uvs = end_ring.add_uvs(mesh, chain.uv_width);
+    let start = start_ring.indices(mesh).to_vec();
+    let end = end_ring.indices(mesh).to_vec();
+
+    mesh.add_band(
+        (&start[..], &start_uvs[..]),
+        (&end[..], &end_uvs[..]),
+        meta.take(),
+    );
+    chain.uvs = Some(Rc::new(end_uvs));
+}
+
+/// Angular positions of a disk's vertices around its center, as described
+/// for `Ring::turns`
+fn turns(disk: &Disk) -> Vec<f32> {
+    let center = disk.iter().map(|&(v, _)| v).sum::<Vec3>()
+        / disk.len().max(1) as f32;
+    let angles: Vec<_> = disk.iter()
+        .map(|&(v, _)| {
+            let offset = v - center;
+            offset.y.atan2(offset.x) / TAU
+        })
+        .collect();
+
+    let mut turns = Vec::<f32>::with_capacity(angles.len() + 1);
+
+    for angle in angles {
+        let turn = match turns.last() {
+            None => angle.rem_euclid(1f32),
+            Some(&prev) => {
+                prev + (angle - prev + 0.5f32).rem_euclid(1f32) - 0.5f32
+            },
+        };
+
+        turns.push(turn);
+    }
+
+    // Close the seam a whole turn on from the first vertex
+    if let (Some(&first), Some(&last)) = (turns.first(), turns.last()) {
+        turns.push(first + 1f32.copysign(last - first));
+    }
+
+    turns
+}
+
+/// Closes a ring whose counter-clockwise winding faces out of the mesh.
+/// Caps get texture coordinate islands of their own, projected onto the
+/// plane of the ring.
+fn add_cap(mesh: &mut Mesh, ring: &[usize], style: CapStyle, meta: MeshMeta) {
+    if ring.len() < 3 {
+        return;
+    }
+
+    let positions: Vec<_> = ring.iter()
+        .map(|&idx| mesh.verts()[idx])
+        .collect();
+
+    let centroid = positions.iter().sum::<Vec3>() / positions.len() as f32;
+    let normal = mesh.face_normal(ring).normalize_or_zero();
+    let tangent = (positions[0] - centroid).normalize_or_zero();
+    let bitangent = normal.cross(tangent);
+
+    let planar: Vec<_> = positions.iter()
+        .map(|&v| {
+            let offset = v - centroid;
+            Vec2::new(offset.dot(tangent), offset.dot(bitangent))
+        })
+        .collect();
+
+    match style {
+        CapStyle::None => (),
+        CapStyle::Flat => {
+            let uvs = mesh.add_uvs(&planar[..]);
+            mesh.add_face(ring.to_vec(), uvs, Some(meta));
+        },
+        CapStyle::Fan => {
+            let center = mesh.add_vert(centroid);
+            let mut uvs = mesh.add_uvs(&planar[..]);
+            uvs.push(uvs[0]);
+            let center_uvs = vec![mesh.add_uvs(&[Vec2::ZERO])[0]; ring.len()];
+            add_fan(
+                mesh,
+                (ring, &uvs[..]),
+                (center, &center_uvs[..]),
+                Some(meta),
+            );
+        },
+        CapStyle::Dome(ring_ct) => {
+            let radius = positions.iter()
+                .map(|v| v.distance(centroid))
+                .sum::<f32>() / positions.len() as f32;
+
+            // Unwrap the planar angles the same way as a disk's
+            let disk: Disk = planar.iter()
+                .map(|uv| (uv.extend(0f32), 0))
+                .collect();
+            let turns = turns(&disk);
+            let width = TAU * radius;
+            let dome_uvs = |mesh: &mut Mesh, arc: f32| {
+                let uvs: Vec<_> = turns.iter()
+                    .map(|&turn| Vec2::new(turn * width, arc))
+                    .collect();
+                mesh.add_uvs(&uvs[..])
+            };
+
+            let mut meta = Some(meta);
+            let mut prev = ring.to_vec();
+            let mut prev_uvs = dome_uvs(mesh, 0f32);
+
+            for ring_idx in 1..=ring_ct {
+                let angle = FRAC_PI_2 * ring_idx as f32 / (ring_ct + 1) as f32;
+                let next_positions: Vec<_> = positions.iter()
+                    .map(|&v| centroid
+                        + (v - centroid) * angle.cos()
+                        + normal * radius * angle.sin())
+                    .collect();
+
+                let next = mesh.add_ring(&next_positions[..]);
+
+                // Carry the creases up to the apex
+                for (&old_idx, &new_idx) in prev.iter().zip(&next) {
+                    if mesh.is_crease(old_idx) {
+                        mesh.mark_crease(new_idx);
+                    }
+                }
+
+                let next_uvs = dome_uvs(mesh, angle * radius);
+                mesh.add_band(
+                    (&prev[..], &prev_uvs[..]),
+                    (&next[..], &next_uvs[..]),
+                    meta.take(),
+                );
+                prev = next;
+                prev_uvs = next_uvs;
+            }
+
+            let apex = mesh.add_vert(centroid + normal * radius);
+            let apex_uvs: Vec<_> = turns.windows(2)
+                .map(|pair| Vec2::new(
+                    (pair[0] + pair[1]) / 2f32 * width,
+                    FRAC_PI_2 * radius,
+                ))
+                .collect();
+            let apex_uvs = mesh.add_uvs(&apex_uvs[..]);
+            add_fan(
+                mesh,
+                (&prev[..], &prev_uvs[..]),
+                (apex, &apex_uvs[..]),
+                meta,
+            );
+        },
+    }
+}
+
+/// Fans a ring out to a center vertex.  The ring's texture coordinates
+/// repeat its first at the end as for a band, and the center takes one
+/// coordinate per triangle.
+fn add_fan(
+    mesh: &mut Mesh,
+    (ring, ring_uvs): (&[usize], &[usize]),
+    (center, center_uvs): (usize, &[usize]),
+    mut meta: Option<MeshMeta>,
+) {
+    for idx in 0..ring.len() {
+        let next_idx = (idx + 1) % ring.len();
+        mesh.add_face(
+            vec![ring[idx], ring[next_idx], center],
+            vec![ring_uvs[idx], ring_uvs[idx + 1], center_uvs[idx]],
+            meta.take(),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fixtures::chain_body;
+    use crate::select::Selection;
+
+    use super::*;
+
+    /// Ring of a disk placed in `frame`, running once around it
+    fn ring(frame: Affine3, local: Vec<Vec3>) -> Ring {
+        let len = local.len();
+
+        Ring {
+            frame,
+            positions: local.iter()
+                .map(|&v| frame.transform_point3(v))
+                .collect(),
+            local,
+            creases: vec![false; len],
+            sources: vec![None; len],
+            turns: (0..=len).map(|idx| idx as f32 / len as f32).collect(),
+            arc_length: 0f32,
+            indices: OnceCell::new(),
+            meta: MeshMeta {
+                body_idx: 0,
+                disk_info_idx: 0,
+                color: None,
+                cap: false,
+                branch: 0,
+            },
+            root: false,
+        }
+    }
+
+    /// Points evenly spaced around a circle about `center`, starting half
+    /// a step past the X axis
+    fn circle(center: Vec3, radius: f32, len: usize) -> Vec<Vec3> {
+        (0..len)
+            .map(|idx| {
+                let angle = (idx as f32 + 0.5f32) * TAU / len as f32;
+                center + Vec3::new(angle.cos(), angle.sin(), 0f32) * radius
+            })
+            .collect()
+    }
+
+    #[test]
+    fn interpolated_rings_have_no_source() {
+        let body = chain_body(3);
+        let options = BuildOptions {
+            smooth_rings: 2,
+            ..BuildOptions::default()
+        };
+        let (mesh, _) = build_mesh(Some(&body), &options);
+
+        // Three disks of four, with two rings between each pair
+        assert_eq!(mesh.verts().len(), 4 * (3 + 2 * 2));
+
+        // Only the disks' own rings, at whole heights, were placed from
+        // disk vertices
+        for (idx, v) in mesh.verts().iter().enumerate() {
+            let source = mesh.source(idx).map(|s| s.vertex_idx);
+            let placed = v.z.fract() == 0f32;
+
+            assert_eq!(source, placed.then_some(idx % 4), "vertex {idx}");
+        }
+    }
+
+    #[test]
+    fn spline_rings() {
+        let square = |size: f32| -> Vec<_> {
+            [Vec3::X, Vec3::Y, -Vec3::X, -Vec3::Y]
+                .map(|v| v * size)
+                .into()
+        };
+        // A straight chain gives evenly spaced rings, blending the disks
+        let start = ring(Affine3::IDENTITY, square(1f32));
+        let end = ring(
+            Affine3::from_translation(Vec3::Z * 2f32),
+            square(3f32),
+        );
+
+        for t in [0.25f32, 0.5f32, 0.75f32] {
+            let mid = start.between(&end, t);
+            let size = 1f32 + 2f32 * t;
+
+            assert!(mid.centroid().abs_diff_eq(Vec3::Z * 2f32 * t, 1e-5));
+            assert!(mid.local[0].abs_diff_eq(Vec3::X * size, 1e-5));
+        }
+
+        // A quarter turn about X follows the bend rather than the chord
+        let end = ring(
+            Affine3::from_rotation_translation(
+                Quat::from_rotation_x(-FRAC_PI_2),
+                Vec3::new(0f32, 1f32, 1f32),
+            ),
+            square(1f32),
+        );
+        let mid = start.between(&end, 0.5f32);
+        let arc_center = Vec3::Y;
+
+        assert!((mid.centroid().distance(arc_center) - 1f32).abs() < 0.05f32);
+        assert!(
+            mid.frame.matrix3.z_axis
+                .abs_diff_eq(Vec3::new(0f32, 1f32, 1f32).normalize(), 1e-5)
+        );
+
+        // The ends are the disks themselves
+        let first = start.between(&end, 0f32);
+        let last = start.between(&end, 1f32);
+        assert!(first.centroid().abs_diff_eq(start.centroid(), 1e-5));
+        assert!(last.centroid().abs_diff_eq(end.centroid(), 1e-5));
+    }
+
+    #[test]
+    fn junction_splits_ring() {
+        let mut mesh = Mesh::default();
+        let trunk = ring(Affine3::IDENTITY, circle(Vec3::ZERO, 1f32, 8));
+        let left = circle(Vec3::new(2f32, 0f32, 1f32), 0.5f32, 4);
+        let right = circle(Vec3::new(-2f32, 0f32, 1f32), 0.5f32, 4);
+
+        let [left_loop, right_loop] = split_ring(
+            &mut mesh,
+            &trunk,
+            [&left[..], &right[..]],
+        ).expect("ring wasn't split");
+
+        // Four vertices face each branch.  The arcs share their ends, and
+        // the gap the left arc leaves is closed by two new vertices, which
+        // both loops run through.
+        let trunk_indices = trunk.indices.get().unwrap();
+        let [left_indices, right_indices] = [&left_loop, &right_loop]
+            .map(|ring| ring.indices.get().unwrap());
+        let split: Vec<_> = (8..10).collect();
+
+        assert_eq!(mesh.verts().len(), 8 + 2);
+        assert_eq!(left_indices.len(), 6 + 2);
+        assert_eq!(right_indices.len(), 4 + 2);
+
+        for idx in trunk_indices.iter().chain(&split) {
+            assert!(
+                left_indices.contains(idx) || right_indices.contains(idx),
+                "vertex {idx} is in neither loop",
+            );
+        }
+
+        for idx in &split {
+            assert!(left_indices.contains(idx) && right_indices.contains(idx));
+        }
+
+        // Each loop lies on its branch's side and keeps the ring's winding
+        assert!(left_loop.centroid().x > 0f32);
+        assert!(right_loop.centroid().x < 0f32);
+
+        for indices in [left_indices, right_indices] {
+            assert!(mesh.face_normal(indices).z > 0f32);
+        }
+
+        // Rings that don't separate into two runs are left alone
+        let across = circle(Vec3::new(0f32, 0f32, 1f32), 0.5f32, 4);
+        assert!(split_ring(&mut mesh, &trunk, [&across[..], &across[..]])
+            .is_none());
+    }
+
+    #[test]
+    fn chains_share_rings() {
+        let body = chain_body(3);
+        let (mesh, _) = build_mesh(Some(&body), &BuildOptions::default());
+
+        // One ring per disk, joined by two bands of quads
+        assert_eq!(mesh.verts().len(), 3 * 4);
+        assert_eq!(mesh.faces().count(), 2 * 4);
+
+        for (face, _) in mesh.faces() {
+            assert_eq!(face.len(), 4);
+        }
+    }
+
+    #[test]
+    fn caps() {
+        let cap = |style| {
+            let mut mesh = Mesh::default();
+            let ring = mesh.add_ring(&[Vec3::X, Vec3::Y, -Vec3::X, -Vec3::Y]);
+            let meta = MeshMeta {
+                body_idx: 0,
+                disk_info_idx: 0,
+                color: None,
+                cap: true,
+                branch: 0,
+            };
+
+            add_cap(&mut mesh, &ring, style, meta);
+            mesh
+        };
+
+        let counts = |mesh: &Mesh| (mesh.verts().len(), mesh.faces().count());
+
+        assert_eq!(counts(&cap(CapStyle::None)), (4, 0));
+        assert_eq!(counts(&cap(CapStyle::Flat)), (4, 1));
+        assert_eq!(counts(&cap(CapStyle::Fan)), (5, 4));
+        // Two rings of four and an apex, closed by quads and a fan
+        assert_eq!(counts(&cap(CapStyle::Dome(2))), (13, 12));
+
+        for style in [CapStyle::Flat, CapStyle::Fan, CapStyle::Dome(2)] {
+            let mesh = cap(style);
+
+            for (face, meta) in mesh.faces() {
+                let center = face.iter()
+                    .map(|&idx| mesh.verts()[idx])
+                    .sum::<Vec3>() / face.len() as f32;
+
+                assert!(meta.is_some_and(|m| m.cap));
+                assert!(mesh.face_normal(face).z > 0f32, "{style:?}");
+                assert!(
+                    mesh.face_normal(face).dot(center) >= 0f32,
+                    "{style:?} face {face:?} points in",
+                );
+            }
+        }
+
+        // The dome is a hemisphere over the ring
+        for v in cap(CapStyle::Dome(2)).verts() {
+            assert!((v.length() - 1f32).abs() < 1e-5, "{v} is off the dome");
+        }
+    }
+
+    #[test]
+    fn disk_info_filter_uses_ids() {
+        let body = chain_body(4);
+        let build = |ids: [i32; 2]| {
+            let options = BuildOptions {
+                selection: Selection {
+                    disk_info_ids: ids.into_iter().collect(),
+                    ..Selection::default()
+                },
+                ..BuildOptions::default()
+            };
+            build_mesh(Some(&body), &options).0
+        };
+
+        // Segments 1 and 2 have ids 11 and 12
+        assert_eq!(heights(&build([11, 12])), [[2f32; 4], [3f32; 4]].concat());
+
+        assert!(build([1, 2]).verts().is_empty());
+    }
+
+    #[test]
+    fn reset_root_starts_at_origin() {
+        let body = chain_body(3);
+        let options = BuildOptions {
+            selection: Selection {
+                root: Some(1),
+                reset_root: true,
+                ..Selection::default()
+            },
+            ..BuildOptions::default()
+        };
+        let (mesh, _) = build_mesh(Some(&body), &options);
+        assert_eq!(heights(&mesh), [[0f32; 4], [1f32; 4]].concat());
+    }
+
+    fn heights(mesh: &Mesh) -> Vec<f32> {
+        mesh.verts().iter().map(|v| v.z).collect()
+    }
+}
@@ -0,0 +1,300 @@
+use std::fmt;
+use std::io::{self, Write};
+
+use glam::{EulerRot, Vec3};
+
+use fmt::{Formatter, Display};
+use crate::skeleton::{Bone, Skeleton};
+use crate::export::Exporter;
+use crate::app_error::AppError;
+
+const DEFAULT_FRAME_TIME: f32 = 1f32 / 30f32;
+
+/// Biovision hierarchy.  Each joint sits in the frame its segment's action
+/// leaves the chain in, offset within its parent's joint by the parent's
+/// disk shift and any shift action.  Segments with rotation actions get one
+/// channel about their action's axis, the root gets position and rotation
+/// channels, and shift segments are fixed.
+pub struct Bvh<'a> {
+    skeleton: &'a Skeleton,
+    /// One row per frame, each holding the values of every channel in
+    /// hierarchy order
+    frames: Vec<Vec<f32>>,
+    frame_time: f32,
+}
+
+impl<'a> Bvh<'a> {
+    /// Checks that every frame holds a value for each channel
+    pub fn with_frames(
+        skeleton: &'a Skeleton,
+        frames: Vec<Vec<f32>>,
+        frame_time: f32,
+    ) -> Result<Self, AppError> {
+        let bvh = Self { skeleton, frames: Vec::new(), frame_time };
+        let channel_ct = bvh.channel_count();
+
+        for (frame_idx, frame) in frames.iter().enumerate() {
+            if frame.len() != channel_ct {
+                return Err(AppError(format!(
+                    "BVH frame {frame_idx} has {} values for {channel_ct} \
+                     channels",
+                    frame.len(),
+                )));
+            }
+        }
+
+        Ok(Self { frames, ..bvh })
+    }
+
+    /// Hierarchy with a single frame holding the bind pose
+    pub fn bind_pose(skeleton: &'a Skeleton) -> Result<Self, AppError> {
+        let frame = skeleton.bones().iter()
+            .flat_map(|bone| {
+                if bone.parent.is_none() {
+                    let position = bone.joint.translation;
+                    let (z, x, y) = bone.joint.matrix3
+                        .to_euler(EulerRot::ZXY);
+                    let rotation = [z, x, y].map(f32::to_degrees);
+
+                    [position.to_array(), rotation].concat()
+                } else if let Some(axis) = bone.rotation_axis {
+                    vec![if axis.negative { -bone.value } else { bone.value }]
+                } else {
+                    Vec::new()
+                }
+            })
+            .collect();
+
+        Self::with_frames(skeleton, vec![frame], DEFAULT_FRAME_TIME)
+    }
+
+    /// Number of values expected in each frame
+    fn channel_count(&self) -> usize {
+        self.skeleton.bones().iter()
+            .map(|bone| channels(bone).len())
+            .sum()
+    }
+
+    /// Position of the joint within its parent's, or in the world for the
+    /// root, which is placed by its channels
+    fn offset(&self, bone: &Bone) -> Vec3 {
+        match bone.parent {
+            Some(parent) => {
+                let parent_joint = self.skeleton.bones()[parent].joint;
+                (parent_joint.inverse() * bone.joint).translation
+            },
+            None => Vec3::ZERO,
+        }
+    }
+
+    fn fmt_joint(
+        &self,
+        formatter: &mut Formatter,
+        bone_idx: usize,
+        depth: usize,
+    ) -> Result<(), fmt::Error> {
+        let bone = &self.skeleton.bones()[bone_idx];
+        let indent = "  ".repeat(depth);
+        let [x, y, z] = self.offset(bone).into();
+        let channels = channels(bone);
+        let keyword = if depth == 0 { "ROOT" } else { "JOINT" };
+
+        writeln!(formatter, "{indent}{keyword} segment_{}", bone.body_idx)?;
+        writeln!(formatter, "{indent}{{")?;
+        writeln!(formatter, "{indent}  OFFSET {x:.6} {y:.6} {z:.6}")?;
+        writeln!(
+            formatter,
+            "{indent}  CHANNELS {}{}",
+            channels.len(),
+            channels.iter().map(|c| format!(" {c}")).collect::<String>(),
+        )?;
+
+        let mut children = self.skeleton.children(bone_idx).peekable();
+
+        if children.peek().is_none() {
+            writeln!(formatter, "{indent}  End Site")?;
+            writeln!(formatter, "{indent}  {{")?;
+            writeln!(formatter, "{indent}    OFFSET 0.0 0.0 0.0")?;
+            writeln!(formatter, "{indent}  }}")?;
+        }
+
+        for child in children {
+            self.fmt_joint(formatter, child, depth + 1)?;
+        }
+
+        writeln!(formatter, "{indent}}}")?;
+
+        Ok(())
+    }
+}
+
+impl Display for Bvh<'_> {
+    fn fmt(&self, formatter: &mut Formatter) -> Result<(), fmt::Error> {
+        writeln!(formatter, "HIERARCHY")?;
+
+        if !self.skeleton.bones().is_empty() {
+            self.fmt_joint(formatter, 0, 0)?;
+        }
+
+        writeln!(formatter, "MOTION")?;
+        writeln!(formatter, "Frames: {}", self.frames.len())?;
+        writeln!(formatter, "Frame Time: {:.6}", self.frame_time)?;
+
+        for frame in &self.frames {
+            let values: Vec<_> = frame.iter()
+                .map(|v| format!("{v:.6}"))
+                .collect();
+
+            writeln!(formatter, "{}", values.join(" "))?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Exporter for Bvh<'_> {
+    fn export(&self, writer: &mut dyn Write) -> io::Result<()> {
+        write!(writer, "{self}")
+    }
+}
+
+fn channels(bone: &Bone) -> &'static [&'static str] {
+    const ROOT: &[&str] = &[
+        "Xposition", "Yposition", "Zposition",
+        "Zrotation", "Xrotation", "Yrotation",
+    ];
+    const ROTATION: [&[&str]; 3] = [
+        &["Xrotation"],
+        &["Yrotation"],
+        &["Zrotation"],
+    ];
+
+    if bone.parent.is_none() {
+        ROOT
+    } else if let Some(axis) = bone.rotation_axis {
+        ROTATION[axis.index]
+    } else {
+        &[]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use glam::{Affine3, Mat3, Vec2};
+
+    use crate::coords::{Axis, CoordinateSystem, Handedness};
+    use crate::select::Selection;
+    use crate::types::cooked::{BodySegment, DiskInformation};
+
+    use super::*;
+
+    /// Rises, then turns about X, Y and Z in turn, each disk shifted off
+    /// the chain
+    fn bent_chain() -> BodySegment {
+        let actions = [(1, 2f32), (2, 30f32), (3, -45f32), (4, 60f32)];
+
+        (0..actions.len()).rev().fold(None, |child, index| {
+            let (action, value) = actions[index];
+
+            Some(BodySegment {
+                index,
+                disk_info: Some(Rc::new(DiskInformation {
+                    index,
+                    shift: Vec2::new(0.5f32, index as f32 * 0.25f32),
+                    scale: Vec2::ONE,
+                    disk: None,
+                    disk_idx: None,
+                    id: index as i32,
+                    flags: 0,
+                    arr1: [0f32; 4],
+                    arr2: [0f32; 4],
+                })),
+                action,
+                value,
+                color: None,
+                left: child.map(Box::new),
+                right: None,
+            })
+        }).unwrap()
+    }
+
+    #[test]
+    fn bind_pose_rebuilds_joints() {
+        let body = bent_chain();
+        let skeleton = Skeleton::from_body(Some(&body), &Selection::default());
+        let left_z_up = CoordinateSystem {
+            handedness: Handedness::Left,
+            ..CoordinateSystem::Z_UP
+        };
+        let y_up = CoordinateSystem {
+            up: Axis::Y,
+            forward: Axis { index: 2, negative: true },
+            ..CoordinateSystem::Z_UP
+        };
+
+        for coords in [
+            CoordinateSystem::Z_UP,
+            CoordinateSystem::Y_UP_SWAPPED,
+            left_z_up,
+            y_up,
+        ] {
+            let skeleton = skeleton.transformed(&coords);
+            let bvh = Bvh::bind_pose(&skeleton).unwrap();
+            let mut values = bvh.frames[0].iter().copied();
+            let mut joints = Vec::<Affine3>::new();
+
+            // Pose each joint the way a BVH reader would
+            for bone in skeleton.bones() {
+                let joint = match bone.parent {
+                    None => {
+                        let mut next = || values.next().unwrap();
+                        let position = Vec3::new(next(), next(), next());
+                        let [z, x, y] = [next(), next(), next()]
+                            .map(f32::to_radians);
+
+                        Affine3::from_mat3_translation(
+                            Mat3::from_euler(EulerRot::ZXY, z, x, y),
+                            position,
+                        )
+                    },
+                    Some(parent) => {
+                        let mut axis = Vec3::ZERO;
+                        let mut angle = 0f32;
+
+                        if let Some(channel) = bone.rotation_axis {
+                            axis[channel.index] = 1f32;
+                            angle = values.next().unwrap().to_radians();
+                        }
+
+                        joints[parent]
+                            * Affine3::from_translation(bvh.offset(bone))
+                            * Affine3::from_axis_angle(axis, angle)
+                    },
+                };
+
+                assert!(
+                    joint.abs_diff_eq(bone.joint, 1e-5),
+                    "segment {} with {coords:?}",
+                    bone.body_idx,
+                );
+                joints.push(joint);
+            }
+
+            assert!(values.next().is_none());
+        }
+    }
+
+    #[test]
+    fn frames_need_every_channel() {
+        let body = bent_chain();
+        let skeleton = Skeleton::from_body(Some(&body), &Selection::default());
+
+        let frames = |len| vec![vec![0f32; 9], vec![0f32; len]];
+
+        // Six for the root and one for each turn
+        assert!(Bvh::with_frames(&skeleton, frames(9), 1f32).is_ok());
+        assert!(Bvh::with_frames(&skeleton, frames(8), 1f32).is_err());
+    }
+}
@@ -0,0 +1,324 @@
+use std::io::{self, Write};
+use std::path::Path;
+
+use glam::{Affine3, Vec2, Vec3};
+
+use crate::types::cooked::BodySegment;
+use crate::sink::{Branch, MeshSink, RingEvent};
+use crate::coords::CoordinateSystem;
+use crate::export::Exporter;
+use crate::view::View;
+
+const SVG_SIZE: f32 = 512f32;
+const SVG_MARGIN: f32 = 16f32;
+
+/// Segment origins along a chain between two branch points
+#[derive(Clone, Debug, Default)]
+pub struct Polyline {
+    /// Body index of the segment at each point.  Branches start at the
+    /// segment they fork from, or whose disk was left out.
+    pub segments: Vec<usize>,
+    pub points: Vec<Vec3>,
+}
+
+/// Path of the body tree, one polyline per branch, numbered as in
+/// `MeshMeta::branch`
+#[derive(Clone, Debug, Default)]
+pub struct Centerline {
+    pub branches: Vec<Polyline>,
+    /// Branch each segment being walked adds its origin to, and whether
+    /// the chain forks after it
+    scopes: Vec<(usize, bool)>,
+}
+
+impl Centerline {
+    /// Copy with the points in other coordinates
+    pub fn transformed(&self, coords: &CoordinateSystem) -> Self {
+        let mut centerline = self.clone();
+
+        for branch in &mut centerline.branches {
+            for point in &mut branch.points {
+                *point = coords.point(*point);
+            }
+        }
+
+        centerline
+    }
+
+    fn new_branch(&mut self, start: Option<(usize, Vec3)>) -> usize {
+        let (segments, points) = start.into_iter().unzip();
+        self.branches.push(Polyline { segments, points });
+        self.branches.len() - 1
+    }
+
+    /// New branch starting at the last point of `parent`
+    fn fork(&mut self, parent: usize) -> usize {
+        let chain = &self.branches[parent];
+        let start = chain.segments.last().copied()
+            .zip(chain.points.last().copied());
+        self.new_branch(start)
+    }
+}
+
+impl MeshSink for Centerline {
+    fn enter_segment(&mut self, segment: &BodySegment, xform: Affine3) {
+        let branch = match self.scopes.last() {
+            Some(&(parent, true)) => self.fork(parent),
+            Some(&(parent, false)) => parent,
+            None => self.new_branch(None),
+        };
+
+        // Children continue from the shifted frame
+        let origin = match segment.disk_info.as_ref() {
+            Some(info) => xform.transform_point3((info.shift, 0f32).into()),
+            None => xform.translation,
+        };
+
+        self.branches[branch].segments.push(segment.index);
+        self.branches[branch].points.push(origin);
+        self.scopes.push((branch, false));
+    }
+
+    fn ring(&mut self, _ring: &RingEvent) {}
+
+    // The mesh numbers the rings after a filtered disk as a new branch
+    fn gap(&mut self) {
+        if let Some(&(parent, _)) = self.scopes.last() {
+            let branch = self.fork(parent);
+            self.scopes.last_mut().unwrap().0 = branch;
+        }
+    }
+
+    fn branch(&mut self, _branch: &Branch) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.1 = true;
+        }
+    }
+
+    fn exit_segment(&mut self) {
+        self.scopes.pop();
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CenterlineFormat {
+    /// Wavefront OBJ with one `l` element per branch
+    Obj,
+    Json,
+    /// Projection in the given view
+    Svg(View),
+}
+
+impl CenterlineFormat {
+    /// Format for a path's extension, JSON if not recognized
+    pub fn from_path(path: &Path, view: View) -> Self {
+        let ext = path.extension()
+            .map(|ext| ext.to_string_lossy().to_ascii_lowercase());
+
+        match ext.as_deref() {
+            Some("obj") => Self::Obj,
+            Some("svg") => Self::Svg(view),
+            _ => Self::Json,
+        }
+    }
+}
+
+pub struct CenterlineFile<'a> {
+    pub centerline: &'a Centerline,
+    pub format: CenterlineFormat,
+}
+
+impl CenterlineFile<'_> {
+    fn write_obj(&self, writer: &mut dyn Write) -> io::Result<()> {
+        let branches = &self.centerline.branches;
+
+        for point in branches.iter().flat_map(|branch| &branch.points) {
+            let [x, y, z] = (*point).into();
+            writeln!(writer, "v {x:.9} {y:.9} {z:.9}")?;
+        }
+
+        let mut start_idx = 1;
+
+        for (branch_idx, branch) in branches.iter().enumerate() {
+            let end_idx = start_idx + branch.points.len();
+
+            if branch.points.len() > 1 {
+                let indices: Vec<_> = (start_idx..end_idx)
+                    .map(|idx| idx.to_string())
+                    .collect();
+
+                writeln!(writer, "o branch_{branch_idx}")?;
+                writeln!(writer, "l {}", indices.join(" "))?;
+            }
+
+            start_idx = end_idx;
+        }
+
+        Ok(())
+    }
+
+    fn write_json(&self, writer: &mut dyn Write) -> io::Result<()> {
+        let branches = &self.centerline.branches;
+
+        writeln!(writer, "[")?;
+
+        for (branch_idx, branch) in branches.iter().enumerate() {
+            let segments: Vec<_> = branch.segments.iter()
+                .map(|idx| idx.to_string())
+                .collect();
+            let points: Vec<_> = branch.points.iter()
+                .map(|p| format!("[{:.6}, {:.6}, {:.6}]", p.x, p.y, p.z))
+                .collect();
+            let comma = if branch_idx + 1 < branches.len() { "," } else { "" };
+
+            writeln!(writer, "  {{")?;
+            writeln!(writer, "    \"branch\": {branch_idx},")?;
+            writeln!(writer, "    \"segments\": [{}],", segments.join(", "))?;
+            writeln!(writer, "    \"points\": [{}]", points.join(", "))?;
+            writeln!(writer, "  }}{comma}")?;
+        }
+
+        writeln!(writer, "]")?;
+
+        Ok(())
+    }
+
+    fn write_svg(&self, writer: &mut dyn Write, view: View) -> io::Result<()> {
+        let branches = &self.centerline.branches;
+        let projected: Vec<Vec<Vec2>> = branches.iter()
+            .map(|branch| {
+                branch.points.iter().map(|&p| view.project(p)).collect()
+            })
+            .collect();
+
+        let (mins, maxs) = projected.iter().flatten().fold(
+            (Vec2::splat(f32::INFINITY), Vec2::splat(f32::NEG_INFINITY)),
+            |(mins, maxs), &p| (mins.min(p), maxs.max(p)),
+        );
+        let (mins, maxs) = if mins.x <= maxs.x {
+            (mins, maxs)
+        } else {
+            (Vec2::ZERO, Vec2::ZERO)
+        };
+
+        // Fit the longer side, flipping Y to run down the page
+        let extent = (maxs - mins).max_element().max(f32::EPSILON);
+        let scale = (SVG_SIZE - 2f32 * SVG_MARGIN) / extent;
+        let size = (maxs - mins) * scale + Vec2::splat(2f32 * SVG_MARGIN);
+        let to_page = |p: Vec2| Vec2::new(
+            SVG_MARGIN + (p.x - mins.x) * scale,
+            SVG_MARGIN + (maxs.y - p.y) * scale,
+        );
+
+        writeln!(
+            writer,
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" \
+             width=\"{:.0}\" height=\"{:.0}\" viewBox=\"0 0 {:.2} {:.2}\">",
+            size.x.ceil(), size.y.ceil(), size.x, size.y,
+        )?;
+        writeln!(writer, "  <title>Centerline, {} view</title>", view.name())?;
+        writeln!(
+            writer,
+            "  <g fill=\"none\" stroke=\"black\" stroke-width=\"2\" \
+             stroke-linejoin=\"round\" stroke-linecap=\"round\">",
+        )?;
+
+        for (branch_idx, points) in projected.iter().enumerate() {
+            let points: Vec<_> = points.iter()
+                .map(|&p| {
+                    let p = to_page(p);
+                    format!("{:.2},{:.2}", p.x, p.y)
+                })
+                .collect();
+
+            writeln!(
+                writer,
+                "    <polyline id=\"branch_{branch_idx}\" points=\"{}\"/>",
+                points.join(" "),
+            )?;
+        }
+
+        writeln!(writer, "  </g>")?;
+        writeln!(writer, "  <g fill=\"red\">")?;
+
+        for (branch_idx, (branch, points)) in branches.iter()
+            .zip(&projected)
+            .enumerate()
+        {
+            // Later branches share their first point with the one they
+            // fork from
+            let skip = if branch_idx > 0 { 1 } else { 0 };
+
+            for (&segment, &p) in branch.segments.iter()
+                .zip(points)
+                .skip(skip)
+            {
+                let p = to_page(p);
+                writeln!(
+                    writer,
+                    "    <circle cx=\"{:.2}\" cy=\"{:.2}\" r=\"3\">\
+                     <title>segment {segment}</title></circle>",
+                    p.x, p.y,
+                )?;
+            }
+        }
+
+        writeln!(writer, "  </g>")?;
+        writeln!(writer, "</svg>")?;
+
+        Ok(())
+    }
+}
+
+impl Exporter for CenterlineFile<'_> {
+    fn export(&self, writer: &mut dyn Write) -> io::Result<()> {
+        match self.format {
+            CenterlineFormat::Obj => self.write_obj(writer),
+            CenterlineFormat::Json => self.write_json(writer),
+            CenterlineFormat::Svg(view) => self.write_svg(writer, view),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::build::{build_mesh, BuildOptions};
+    use crate::fixtures::chain_body;
+    use crate::select::Selection;
+    use crate::sink::walk_body;
+
+    use super::*;
+
+    #[test]
+    fn branches_match_mesh() {
+        let body = chain_body(4);
+        // Leaving out the disk of segment 1 splits the chain in two
+        let selection = Selection {
+            colors: [0, 2, 3].into_iter().collect(),
+            ..Selection::default()
+        };
+        let options = BuildOptions {
+            selection: selection.clone(),
+            ..BuildOptions::default()
+        };
+        let (mesh, _) = build_mesh(Some(&body), &options);
+        let mut centerline = Centerline::default();
+        walk_body(Some(&body), &selection, &mut centerline);
+
+        let segments: Vec<_> = centerline.branches.iter()
+            .map(|branch| branch.segments.clone())
+            .collect();
+        assert_eq!(segments, [vec![0, 1], vec![1, 2, 3]]);
+
+        let mut faces = 0;
+
+        for (_, meta) in mesh.faces() {
+            let meta = meta.expect("face without metadata");
+            let branch = &centerline.branches[meta.branch];
+            assert!(branch.segments.contains(&meta.body_idx));
+            faces+= 1;
+        }
+
+        assert!(faces > 0);
+    }
+}
@@ -0,0 +1,913 @@
+use std::process;
+use std::ffi::OsString;
+use std::str::FromStr;
+use std::path::{Path, PathBuf};
+
+use crate::app_error::AppError;
+use crate::build::{BuildOptions, CapStyle, JunctionStyle, UnionStyle};
+use crate::union::DEFAULT_RESOLUTION;
+use crate::types::Triangulation;
+use crate::coords::{Axis, CoordinateSystem, Handedness};
+use crate::obj::{Grouping, ObjGrouping};
+use crate::view::View;
+use crate::render::{Camera, RenderSettings, Shading};
+
+const USAGE: &str = "\
+Usage: de3da [convert] <input> [options] [build options]
+       de3da blueprint <input> [blueprint options] [build options]
+       de3da render <input> [render options] [build options]
+       de3da turntable <input> [turntable options] [render options]
+                       [build options]
+       de3da tree <input> [tree options]
+
+Options:
+    -h, --help              Print this message
+    -o, --output <path>     Write to <path> instead of standard output
+    -f, --format <format>   Output format: obj, mdl, smd, smd-anim, bvh
+                            or usda (default: from the output extension,
+                            otherwise obj)
+    --mtl <path>            Write materials to <path> (default: next to the
+                            output file, with an .mtl extension)
+    --palette <path>        Read segment colors from a palette file
+    --vertex-map <path>     Also write which body segment and disk vertex
+                            each output vertex came from, as CSV if <path>
+                            ends in .csv, otherwise JSON.  SMD vertices are
+                            triangle corners; smd-anim and bvh hold no
+                            vertices, so they have no map
+    --centerline <path>     Also write the path of the body tree, as OBJ
+                            lines, an SVG drawing or otherwise JSON,
+                            depending on the extension of <path>
+    --view <view>           Draw SVG output from the front, side or top
+                            (default: side)
+    --objects <mode>        Split OBJ output into objects per loop, branch
+                            or color, or none (default: none)
+    --object-name <template>
+                            Name objects with {body}, {disk_info}, {color}
+                            and {branch} filled in from their first loop
+    --groups <mode>         Split OBJ output into groups per loop, branch
+                            or color, or none (default: loop)
+    --group-name <template> Name groups like --object-name
+    --up <axis>             Up axis of the output: y or z (default for obj
+                            and bvh: y, otherwise z)
+    --forward <axis>        Axis the model faces along, such as z or -x
+                            (default: z when up is y, otherwise y)
+    --handedness <hand>     Right or left (default for obj and bvh: left
+                            unless --up is given, otherwise right)
+    --scale <factor>        Output units per model unit (default: 1)
+
+Build options:
+    --weld <tolerance>      Merge vertices closer than <tolerance>
+    --triangulate <mode>    Split faces into triangles along the fixed or
+                            the shortest diagonal (default for mdl and smd:
+                            fixed)
+    --smooth <rings>        Interpolate this many rings between consecutive
+                            disks along a spline through the segments
+    --subdivide <levels>    Apply Catmull-Clark subdivision this many times
+    --cap <style>           Close chain ends: none, flat, fan or dome
+                            (default: none)
+    --dome-rings <count>    Rings between the rim and apex of dome caps
+                            (default: 3)
+    --union                 Merge the tubes into one closed shell, cutting
+                            faces where tubes cross and dropping the parts
+                            inside other tubes
+    --voxel-union           Like --union, but remesh the whole shell from a
+                            grid of the space the tubes enclose.  Detail
+                            finer than a cell, texture coordinates and
+                            vertex sources are lost and faces grow in
+                            number, but crossings too tangled to cut
+                            cleanly still give a closed shell
+    --voxel-resolution <cells>
+                            Grid cells along the longest side of the model
+                            for --voxel-union (default: 64)
+    --junction <style>      Where branches meet: overlap, or stitch them
+                            into the ring they leave (default: overlap)
+    --root <index>          Export only the subtree from this body segment
+    --reset-root            Place the --root segment's disk at the origin
+                            instead of where it sits in the whole model
+    --exclude <index>       Leave out the subtree from this body segment;
+                            may be repeated
+    --color <index>         Keep only the disks of segments with this
+                            color; may be repeated
+    --disk-info <id>        Keep only the disks placed through the disk
+                            information entry with this id (not its
+                            position in the file); may be repeated
+
+Blueprint options:
+    -o, --output <path>     Write the SVG to <path> instead of standard
+                            output
+    --palette <path>        Read segment colors from a palette file
+    --view <view>           Draw the front, side or top view; may be
+                            repeated (default: side, front and top)
+    --silhouette            Fill the outline instead of drawing faces
+    --withers <index>       Also mark the height of the top of this body
+                            segment above the lowest point
+
+Render options:
+    -o, --output <path>     Write the PNG to <path> instead of standard
+                            output
+    --palette <path>        Read segment colors from a palette file
+    --view <view>           Look from the front, side or top (default:
+                            side)
+    --yaw <degrees>         Turn the camera counter-clockwise about the
+                            vertical axis from the --view
+    --pitch <degrees>       Raise the camera from the --view
+    --size <size>           Image size as <width>x<height>, or one number
+                            for a square (default: 256)
+    --shading <mode>        Flat or gouraud (default: gouraud)
+    --background <color>    Fill behind the model with an rrggbb color
+                            (default: transparent)
+
+Turntable options:
+    -o, --output <path>     Write an animated PNG to <path> instead of
+                            standard output, or numbered PNGs if <path>
+                            holds {frame}, such as turn_{frame}.png
+    --frames <count>        Frames in a full turn about the vertical axis,
+                            starting from the render camera (default: 36)
+    --fps <rate>            Frames per second of the animation (default:
+                            12)
+
+Tree options:
+    -o, --output <path>     Write to <path> instead of standard output
+    --dot                   Write a Graphviz DOT graph instead of text";
+
+pub enum Command {
+    Convert(Box<Options>),
+    Blueprint(Box<BlueprintOptions>),
+    Render(Box<RenderOptions>),
+    Turntable(Box<TurntableOptions>),
+    Tree(TreeOptions),
+}
+
+impl Command {
+    pub fn parse(
+        args: impl Iterator<Item = OsString>
+    ) -> Result<Self, AppError> {
+        let mut args = args.peekable();
+
+        match args.peek().and_then(|arg| arg.to_str()) {
+            Some("convert") => {
+                args.next();
+                Options::parse(args).map(Box::new).map(Self::Convert)
+            },
+            Some("blueprint") => {
+                args.next();
+                BlueprintOptions::parse(args).map(Box::new).map(Self::Blueprint)
+            },
+            Some("render") => {
+                args.next();
+                RenderOptions::parse(args).map(Box::new).map(Self::Render)
+            },
+            Some("turntable") => {
+                args.next();
+                TurntableOptions::parse(args)
+                    .map(Box::new)
+                    .map(Self::Turntable)
+            },
+            Some("tree") => {
+                args.next();
+                TreeOptions::parse(args).map(Self::Tree)
+            },
+            _ => Options::parse(args).map(Box::new).map(Self::Convert),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Obj,
+    Mdl,
+    Smd,
+    SmdAnim,
+    Bvh,
+    Usda,
+}
+
+impl Format {
+    /// Whether the format can only hold triangles
+    pub fn needs_triangles(&self) -> bool {
+        matches!(self, Self::Mdl | Self::Smd)
+    }
+
+    /// Whether the format holds the mesh rather than just the skeleton
+    pub fn has_mesh(&self) -> bool {
+        !matches!(self, Self::SmdAnim | Self::Bvh)
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "obj" => Some(Self::Obj),
+            "mdl" => Some(Self::Mdl),
+            "smd" => Some(Self::Smd),
+            "smd-anim" => Some(Self::SmdAnim),
+            "bvh" => Some(Self::Bvh),
+            "usda" => Some(Self::Usda),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Options {
+    pub input: PathBuf,
+    pub output: Option<PathBuf>,
+    pub format: Option<Format>,
+    pub mtl: Option<PathBuf>,
+    pub palette: Option<PathBuf>,
+    pub vertex_map: Option<PathBuf>,
+    pub centerline: Option<PathBuf>,
+    pub view: View,
+    pub build: BuildOptions,
+    pub coords: CoordinateSystem,
+    pub obj: ObjGrouping,
+}
+
+impl Options {
+    pub fn parse(
+        mut args: impl Iterator<Item = OsString>
+    ) -> Result<Self, AppError> {
+        let mut input = None;
+        let mut build = BuildFlags::default();
+        let mut up = None;
+        let mut forward = None;
+        let mut handedness = None;
+        let mut scale = None;
+        let mut options = Self::default();
+
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| args.next()
+                .map(PathBuf::from)
+                .ok_or(AppError(format!("Missing value for {name}")));
+
+            match arg.to_str() {
+                Some("-h" | "--help") => print_usage(),
+                Some("-o" | "--output") => {
+                    options.output = Some(value("--output")?);
+                },
+                Some("-f" | "--format") => {
+                    let name = value("--format")?;
+                    let name = name.to_string_lossy();
+
+                    options.format = Some(
+                        Format::from_name(&name).ok_or(
+                            AppError(format!("Unrecognized format {name}"))
+                        )?
+                    );
+                },
+                Some("--mtl") => {
+                    options.mtl = Some(value("--mtl")?);
+                },
+                Some("--palette") => {
+                    options.palette = Some(value("--palette")?);
+                },
+                Some("--vertex-map") => {
+                    options.vertex_map = Some(value("--vertex-map")?);
+                },
+                Some("--centerline") => {
+                    options.centerline = Some(value("--centerline")?);
+                },
+                Some("--view") => {
+                    let name = value("--view")?;
+                    let name = name.to_string_lossy();
+
+                    options.view = View::from_name(&name).ok_or(
+                        AppError(format!("Unrecognized view {name}"))
+                    )?;
+                },
+                Some("--objects") => {
+                    options.obj.objects = parse_grouping(&value("--objects")?)?;
+                },
+                Some("--object-name") => {
+                    options.obj.object_name = Some(
+                        value("--object-name")?.to_string_lossy().into_owned()
+                    );
+                },
+                Some("--groups") => {
+                    options.obj.groups = parse_grouping(&value("--groups")?)?;
+                },
+                Some("--group-name") => {
+                    options.obj.group_name = Some(
+                        value("--group-name")?.to_string_lossy().into_owned()
+                    );
+                },
+                Some("--up") => {
+                    let name = value("--up")?;
+                    let name = name.to_string_lossy();
+
+                    up = Some(
+                        Axis::from_name(&name)
+                            .filter(|axis| matches!(axis.index, 1 | 2))
+                            .filter(|axis| !axis.negative)
+                            .ok_or(
+                                AppError(format!("Unrecognized up axis {name}"))
+                            )?
+                    );
+                },
+                Some("--forward") => {
+                    let name = value("--forward")?;
+                    let name = name.to_string_lossy();
+
+                    forward = Some(
+                        Axis::from_name(&name).ok_or(
+                            AppError(format!("Unrecognized axis {name}"))
+                        )?
+                    );
+                },
+                Some("--handedness") => {
+                    let name = value("--handedness")?;
+                    let name = name.to_string_lossy();
+
+                    handedness = Some(
+                        Handedness::from_name(&name).ok_or(
+                            AppError(format!("Unrecognized handedness {name}"))
+                        )?
+                    );
+                },
+                Some("--scale") => {
+                    scale = Some(parse_number(&value("--scale")?, "--scale")?);
+                },
+                Some(flag) if is_flag(flag) => {
+                    if !build.parse(flag, &mut value)? {
+                        return Err(unrecognized(flag));
+                    }
+                },
+                _ => set_input(&mut input, arg)?,
+            }
+        }
+
+        options.input = require_input(input)?;
+        options.build = build.finish();
+
+        let format = options.format();
+
+        if options.vertex_map.is_some() && !format.has_mesh() {
+            return Err(AppError(
+                "--vertex-map needs obj, mdl, smd or usda output".into()
+            ));
+        }
+
+        let mut coords = match (up, format) {
+            (None, Format::Obj | Format::Bvh) => CoordinateSystem::Y_UP_SWAPPED,
+            _ => CoordinateSystem::Z_UP,
+        };
+
+        if let Some(up) = up {
+            coords.up = up;
+            coords.forward = if up == Axis::Y { Axis::Z } else { Axis::Y };
+        }
+
+        coords.forward = forward.unwrap_or(coords.forward);
+        coords.handedness = handedness.unwrap_or(coords.handedness);
+        coords.scale = scale.unwrap_or(coords.scale);
+
+        if coords.forward.index == coords.up.index {
+            return Err(AppError(format!(
+                "Forward axis {} is parallel to the up axis",
+                coords.forward.name(),
+            )));
+        }
+
+        options.coords = coords;
+
+        Ok(options)
+    }
+
+    pub fn format(&self) -> Format {
+        self.format
+            .or_else(|| self.output.as_ref()
+                .and_then(|out| out.extension())
+                .and_then(|ext| Format::from_name(&ext.to_string_lossy()))
+            )
+            .unwrap_or(Format::Obj)
+    }
+
+    /// Material library path, if materials are to be written
+    pub fn mtl_path(&self) -> Option<PathBuf> {
+        self.mtl.clone().or_else(
+            || self.output.as_ref().map(|out| out.with_extension("mtl"))
+        )
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct BlueprintOptions {
+    pub input: PathBuf,
+    pub output: Option<PathBuf>,
+    pub palette: Option<PathBuf>,
+    pub views: Vec<View>,
+    pub silhouette: bool,
+    /// Body segment at the top of the withers
+    pub withers: Option<usize>,
+    pub build: BuildOptions,
+}
+
+impl BlueprintOptions {
+    pub fn parse(
+        mut args: impl Iterator<Item = OsString>
+    ) -> Result<Self, AppError> {
+        let mut input = None;
+        let mut build = BuildFlags::default();
+        let mut views = Vec::new();
+        let mut options = Self::default();
+
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| args.next()
+                .map(PathBuf::from)
+                .ok_or(AppError(format!("Missing value for {name}")));
+
+            match arg.to_str() {
+                Some("-h" | "--help") => print_usage(),
+                Some("-o" | "--output") => {
+                    options.output = Some(value("--output")?);
+                },
+                Some("--palette") => {
+                    options.palette = Some(value("--palette")?);
+                },
+                Some("--view") => {
+                    let name = value("--view")?;
+                    let name = name.to_string_lossy();
+
+                    views.push(View::from_name(&name).ok_or(
+                        AppError(format!("Unrecognized view {name}"))
+                    )?);
+                },
+                Some("--silhouette") => {
+                    options.silhouette = true;
+                },
+                Some("--withers") => {
+                    options.withers = Some(
+                        parse_number(&value("--withers")?, "--withers")?
+                    );
+                },
+                Some(flag) if is_flag(flag) => {
+                    if !build.parse(flag, &mut value)? {
+                        return Err(unrecognized(flag));
+                    }
+                },
+                _ => set_input(&mut input, arg)?,
+            }
+        }
+
+        if views.is_empty() {
+            views = vec![View::Side, View::Front, View::Top];
+        }
+
+        options.input = require_input(input)?;
+        options.views = views;
+        options.build = build.finish();
+
+        Ok(options)
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct RenderOptions {
+    pub input: PathBuf,
+    pub output: Option<PathBuf>,
+    pub palette: Option<PathBuf>,
+    pub settings: RenderSettings,
+    pub build: BuildOptions,
+}
+
+impl RenderOptions {
+    pub fn parse(
+        mut args: impl Iterator<Item = OsString>
+    ) -> Result<Self, AppError> {
+        let mut input = None;
+        let mut build = BuildFlags::default();
+        let mut render = RenderFlags::default();
+        let mut options = Self::default();
+
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| args.next()
+                .map(PathBuf::from)
+                .ok_or(AppError(format!("Missing value for {name}")));
+
+            match arg.to_str() {
+                Some("-h" | "--help") => print_usage(),
+                Some("-o" | "--output") => {
+                    options.output = Some(value("--output")?);
+                },
+                Some("--palette") => {
+                    options.palette = Some(value("--palette")?);
+                },
+                Some(flag) if is_flag(flag) => {
+                    if !render.parse(flag, &mut value)?
+                        && !build.parse(flag, &mut value)?
+                    {
+                        return Err(unrecognized(flag));
+                    }
+                },
+                _ => set_input(&mut input, arg)?,
+            }
+        }
+
+        options.input = require_input(input)?;
+        options.settings = render.finish();
+        options.build = build.finish();
+
+        Ok(options)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct TurntableOptions {
+    pub input: PathBuf,
+    /// Animated PNG, or numbered PNGs if it holds `{frame}`
+    pub output: Option<PathBuf>,
+    pub palette: Option<PathBuf>,
+    /// Camera and look of the first frame
+    pub settings: RenderSettings,
+    pub frames: usize,
+    pub fps: u16,
+    pub build: BuildOptions,
+}
+
+impl Default for TurntableOptions {
+    fn default() -> Self {
+        Self {
+            input: PathBuf::new(),
+            output: None,
+            palette: None,
+            settings: RenderSettings::default(),
+            frames: 36,
+            fps: 12,
+            build: BuildOptions::default(),
+        }
+    }
+}
+
+impl TurntableOptions {
+    pub fn parse(
+        mut args: impl Iterator<Item = OsString>
+    ) -> Result<Self, AppError> {
+        let mut input = None;
+        let mut build = BuildFlags::default();
+        let mut render = RenderFlags::default();
+        let mut options = Self::default();
+
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| args.next()
+                .map(PathBuf::from)
+                .ok_or(AppError(format!("Missing value for {name}")));
+
+            match arg.to_str() {
+                Some("-h" | "--help") => print_usage(),
+                Some("-o" | "--output") => {
+                    options.output = Some(value("--output")?);
+                },
+                Some("--palette") => {
+                    options.palette = Some(value("--palette")?);
+                },
+                Some("--frames") => {
+                    options.frames = parse_number(
+                        &value("--frames")?,
+                        "--frames",
+                    )?;
+                },
+                Some("--fps") => {
+                    options.fps = parse_number(&value("--fps")?, "--fps")?;
+                },
+                Some(flag) if is_flag(flag) => {
+                    if !render.parse(flag, &mut value)?
+                        && !build.parse(flag, &mut value)?
+                    {
+                        return Err(unrecognized(flag));
+                    }
+                },
+                _ => set_input(&mut input, arg)?,
+            }
+        }
+
+        if options.frames == 0 || options.fps == 0 {
+            return Err(AppError(
+                "Frame count and rate must be at least 1".into()
+            ));
+        }
+
+        options.input = require_input(input)?;
+        options.settings = render.finish();
+        options.build = build.finish();
+
+        Ok(options)
+    }
+
+    /// Path of one frame when writing numbered PNGs
+    pub fn frame_path(&self, frame: usize) -> Option<PathBuf> {
+        let template = self.output.as_ref()?.to_str()?;
+
+        if !template.contains("{frame}") {
+            return None;
+        }
+
+        let digits = (self.frames - 1).max(1).ilog10() as usize + 1;
+        let number = format!("{frame:0digits$}");
+
+        Some(PathBuf::from(template.replace("{frame}", &number)))
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct TreeOptions {
+    pub input: PathBuf,
+    pub output: Option<PathBuf>,
+    pub dot: bool,
+}
+
+impl TreeOptions {
+    pub fn parse(
+        mut args: impl Iterator<Item = OsString>
+    ) -> Result<Self, AppError> {
+        let mut input = None;
+        let mut options = Self::default();
+
+        while let Some(arg) = args.next() {
+            let mut value = |name: &str| args.next()
+                .map(PathBuf::from)
+                .ok_or(AppError(format!("Missing value for {name}")));
+
+            match arg.to_str() {
+                Some("-h" | "--help") => print_usage(),
+                Some("-o" | "--output") => {
+                    options.output = Some(value("--output")?);
+                },
+                Some("--dot") => {
+                    options.dot = true;
+                },
+                Some(flag) if is_flag(flag) => return Err(unrecognized(flag)),
+                _ => set_input(&mut input, arg)?,
+            }
+        }
+
+        options.input = require_input(input)?;
+
+        Ok(options)
+    }
+}
+
+/// Options shared by every command that builds a mesh
+#[derive(Default)]
+struct BuildFlags {
+    build: BuildOptions,
+    dome_rings: Option<usize>,
+    voxel_resolution: Option<usize>,
+}
+
+impl BuildFlags {
+    /// Applies a build option, returning whether `flag` was one
+    fn parse(
+        &mut self,
+        flag: &str,
+        value: &mut dyn FnMut(&str) -> Result<PathBuf, AppError>,
+    ) -> Result<bool, AppError> {
+        match flag {
+            "--weld" => {
+                self.build.weld = Some(
+                    parse_number(&value("--weld")?, "--weld")?
+                );
+            },
+            "--triangulate" => {
+                let name = value("--triangulate")?;
+                let name = name.to_string_lossy();
+
+                self.build.triangulate = Some(
+                    Triangulation::from_name(&name).ok_or(
+                        AppError(format!("Unrecognized mode {name}"))
+                    )?
+                );
+            },
+            "--smooth" => {
+                self.build.smooth_rings = parse_number(
+                    &value("--smooth")?,
+                    "--smooth",
+                )?;
+            },
+            "--subdivide" => {
+                self.build.subdivide = parse_number(
+                    &value("--subdivide")?,
+                    "--subdivide",
+                )?;
+            },
+            "--cap" => {
+                let name = value("--cap")?;
+                let name = name.to_string_lossy();
+
+                self.build.cap = CapStyle::from_name(&name).ok_or(
+                    AppError(format!("Unrecognized cap style {name}"))
+                )?;
+            },
+            "--dome-rings" => {
+                self.dome_rings = Some(
+                    parse_number(&value("--dome-rings")?, "--dome-rings")?
+                );
+            },
+            "--union" => {
+                self.build.union = Some(UnionStyle::Boolean);
+            },
+            "--voxel-union" => {
+                self.build.union = Some(UnionStyle::Voxel(DEFAULT_RESOLUTION));
+            },
+            "--voxel-resolution" => {
+                self.voxel_resolution = Some(parse_number(
+                    &value("--voxel-resolution")?,
+                    "--voxel-resolution",
+                )?);
+            },
+            "--junction" => {
+                let name = value("--junction")?;
+                let name = name.to_string_lossy();
+
+                self.build.junction = JunctionStyle::from_name(&name)
+                    .ok_or(
+                        AppError(format!("Unrecognized junction {name}"))
+                    )?;
+            },
+            "--root" => {
+                self.build.selection.root = Some(
+                    parse_number(&value("--root")?, "--root")?
+                );
+            },
+            "--reset-root" => {
+                self.build.selection.reset_root = true;
+            },
+            "--exclude" => {
+                self.build.selection.exclude.insert(
+                    parse_number(&value("--exclude")?, "--exclude")?
+                );
+            },
+            "--color" => {
+                self.build.selection.colors.insert(
+                    parse_number(&value("--color")?, "--color")?
+                );
+            },
+            "--disk-info" => {
+                self.build.selection.disk_info_ids.insert(
+                    parse_number(&value("--disk-info")?, "--disk-info")?
+                );
+            },
+            _ => return Ok(false),
+        }
+
+        Ok(true)
+    }
+
+    fn finish(mut self) -> BuildOptions {
+        if let CapStyle::Dome(_) = self.build.cap
+            && let Some(rings) = self.dome_rings
+        {
+            self.build.cap = CapStyle::Dome(rings);
+        }
+
+        if let Some(UnionStyle::Voxel(_)) = self.build.union
+            && let Some(resolution) = self.voxel_resolution
+        {
+            self.build.union = Some(UnionStyle::Voxel(resolution));
+        }
+
+        self.build
+    }
+}
+
+/// Options shared by every command that renders images
+#[derive(Default)]
+struct RenderFlags {
+    settings: RenderSettings,
+    view: View,
+    yaw: f32,
+    pitch: f32,
+}
+
+impl RenderFlags {
+    /// Applies a render option, returning whether `flag` was one
+    fn parse(
+        &mut self,
+        flag: &str,
+        value: &mut dyn FnMut(&str) -> Result<PathBuf, AppError>,
+    ) -> Result<bool, AppError> {
+        match flag {
+            "--view" => {
+                let name = value("--view")?;
+                let name = name.to_string_lossy();
+
+                self.view = View::from_name(&name).ok_or(
+                    AppError(format!("Unrecognized view {name}"))
+                )?;
+            },
+            "--yaw" => {
+                self.yaw = parse_number(&value("--yaw")?, "--yaw")?;
+            },
+            "--pitch" => {
+                self.pitch = parse_number(&value("--pitch")?, "--pitch")?;
+            },
+            "--size" => {
+                let (width, height) = parse_size(&value("--size")?)?;
+
+                self.settings.width = width;
+                self.settings.height = height;
+            },
+            "--shading" => {
+                let name = value("--shading")?;
+                let name = name.to_string_lossy();
+
+                self.settings.shading = Shading::from_name(&name).ok_or(
+                    AppError(format!("Unrecognized shading {name}"))
+                )?;
+            },
+            "--background" => {
+                let [r, g, b] = parse_color(&value("--background")?)?;
+
+                self.settings.background = [r, g, b, 255];
+            },
+            _ => return Ok(false),
+        }
+
+        Ok(true)
+    }
+
+    fn finish(mut self) -> RenderSettings {
+        let camera = Camera::from_view(self.view);
+
+        self.settings.camera = Camera {
+            yaw: camera.yaw + self.yaw,
+            pitch: camera.pitch + self.pitch,
+        };
+
+        self.settings
+    }
+}
+
+fn parse_number<T: FromStr>(value: &Path, name: &str) -> Result<T, AppError> {
+    value.to_str()
+        .and_then(|v| T::from_str(v).ok())
+        .ok_or(AppError(format!("Invalid value for {name}: {value:?}")))
+}
+
+/// Image size as `<width>x<height>`, or one number for a square
+fn parse_size(value: &Path) -> Result<(usize, usize), AppError> {
+    let size = value.to_string_lossy();
+    let invalid = || AppError(format!("Invalid value for --size: {size}"));
+
+    let (width, height) = match size.split_once(['x', 'X']) {
+        Some((width, height)) => (width.parse(), height.parse()),
+        None => (size.parse(), size.parse()),
+    };
+
+    match (width, height) {
+        (Ok(width), Ok(height)) if width > 0 && height > 0 => {
+            Ok((width, height))
+        },
+        _ => Err(invalid()),
+    }
+}
+
+/// Color written as `rrggbb`, with or without a leading `#`
+fn parse_color(value: &Path) -> Result<[u8; 3], AppError> {
+    let color = value.to_string_lossy();
+    let hex = color.strip_prefix('#').unwrap_or(&color);
+
+    match u32::from_str_radix(hex, 16) {
+        Ok(rgb) if hex.len() == 6 => {
+            let [_, r, g, b] = rgb.to_be_bytes();
+            Ok([r, g, b])
+        },
+        _ => Err(AppError(format!("Invalid color {color}"))),
+    }
+}
+
+fn parse_grouping(value: &Path) -> Result<Grouping, AppError> {
+    let name = value.to_string_lossy();
+
+    Grouping::from_name(&name).ok_or(
+        AppError(format!("Unrecognized grouping {name}"))
+    )
+}
+
+fn print_usage() -> ! {
+    println!("{USAGE}");
+    process::exit(0);
+}
+
+fn is_flag(arg: &str) -> bool {
+    arg.starts_with('-') && arg.len() > 1
+}
+
+fn unrecognized(flag: &str) -> AppError {
+    AppError(format!("Unrecognized option {flag}, see --help"))
+}
+
+fn set_input(
+    input: &mut Option<PathBuf>,
+    arg: OsString,
+) -> Result<(), AppError> {
+    if input.is_some() {
+        Err(AppError(format!("Unexpected argument {arg:?}, see --help")))
+    } else {
+        *input = Some(PathBuf::from(arg));
+        Ok(())
+    }
+}
+
+fn require_input(input: Option<PathBuf>) -> Result<PathBuf, AppError> {
+    input.ok_or(
+        AppError("Provide argument for input path, see --help".into())
+    )
+}
@@ -0,0 +1,156 @@
+use glam::{Affine3, Mat3, Vec3};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Handedness {
+    Right,
+    Left,
+}
+
+impl Handedness {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "right" => Some(Self::Right),
+            "left" => Some(Self::Left),
+            _ => None,
+        }
+    }
+}
+
+/// Unit vector along one of the coordinate axes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Axis {
+    pub index: usize,
+    pub negative: bool,
+}
+
+impl Axis {
+    pub const X: Self = Self { index: 0, negative: false };
+    pub const Y: Self = Self { index: 1, negative: false };
+    pub const Z: Self = Self { index: 2, negative: false };
+
+    /// Parses an axis such as `y`, `+z` or `-x`
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name = name.to_ascii_lowercase();
+        let (negative, letter) = match name.strip_prefix('-') {
+            Some(letter) => (true, letter),
+            None => (false, name.strip_prefix('+').unwrap_or(&name)),
+        };
+
+        let index = match letter {
+            "x" => 0,
+            "y" => 1,
+            "z" => 2,
+            _ => return None,
+        };
+
+        Some(Self { index, negative })
+    }
+
+    pub fn name(&self) -> &'static str {
+        ["X", "Y", "Z"][self.index]
+    }
+
+    fn vector(self) -> Vec3 {
+        let mut v = Vec3::ZERO;
+        v[self.index] = if self.negative { -1f32 } else { 1f32 };
+        v
+    }
+
+    fn cross(self, other: Self) -> Self {
+        let v = self.vector().cross(other.vector());
+        let index = v.abs().max_position();
+
+        Self { index, negative: v[index] < 0f32 }
+    }
+}
+
+/// Axes and units to export in.  Models are built Z-up and right-handed,
+/// facing along Y.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CoordinateSystem {
+    pub up: Axis,
+    pub forward: Axis,
+    pub handedness: Handedness,
+    /// Units per model unit
+    pub scale: f32,
+}
+
+impl CoordinateSystem {
+    /// The models' own axes
+    pub const Z_UP: Self = Self {
+        up: Axis::Z,
+        forward: Axis::Y,
+        handedness: Handedness::Right,
+        scale: 1f32,
+    };
+
+    /// Y-up with Y and Z swapped, mirroring the model, as OBJ and BVH
+    /// output has always been written
+    pub const Y_UP_SWAPPED: Self = Self {
+        up: Axis::Y,
+        forward: Axis::Z,
+        handedness: Handedness::Left,
+        scale: 1f32,
+    };
+
+    /// Where the model's X, Y and Z axes end up
+    fn axes(&self) -> [Axis; 3] {
+        let right = match self.handedness {
+            Handedness::Right => self.forward.cross(self.up),
+            Handedness::Left => self.up.cross(self.forward),
+        };
+
+        [right, self.forward, self.up]
+    }
+
+    /// Rotation, or reflection, from the model's axes
+    pub fn matrix(&self) -> Mat3 {
+        let [x, y, z] = self.axes().map(Axis::vector);
+        Mat3::from_cols(x, y, z)
+    }
+
+    /// Reorders and negates components without arithmetic, so that the
+    /// default axes leave values untouched
+    pub fn direction(&self, v: Vec3) -> Vec3 {
+        let mut out = Vec3::ZERO;
+
+        for (idx, axis) in self.axes().into_iter().enumerate() {
+            out[axis.index] = if axis.negative { -v[idx] } else { v[idx] };
+        }
+
+        out
+    }
+
+    pub fn point(&self, v: Vec3) -> Vec3 {
+        self.direction(v) * self.scale
+    }
+
+    /// Transform between two frames of the model, between the same frames
+    /// in these coordinates
+    pub fn transform(&self, xform: Affine3) -> Affine3 {
+        let matrix = self.matrix();
+
+        Affine3 {
+            matrix3: matrix * xform.matrix3 * matrix.transpose(),
+            translation: self.point(xform.translation),
+        }
+    }
+
+    /// Where one of the model's axes ends up
+    pub fn axis(&self, axis: Axis) -> Axis {
+        let out = self.axes()[axis.index];
+        Axis { negative: out.negative != axis.negative, ..out }
+    }
+
+    /// Whether the axes mirror the model, so faces have to be flipped to
+    /// keep facing out
+    pub fn mirrors(&self) -> bool {
+        self.matrix().determinant() < 0f32
+    }
+}
+
+impl Default for CoordinateSystem {
+    fn default() -> Self {
+        Self::Z_UP
+    }
+}
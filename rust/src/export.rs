@@ -0,0 +1,7 @@
+use std::io::{self, Write};
+
+/// Output format that streams to any writer, so failed writes, such as to
+/// a closed pipe, come back as errors
+pub trait Exporter {
+    fn export(&self, writer: &mut dyn Write) -> io::Result<()>;
+}
@@ -0,0 +1,97 @@
+//! Small hand-made models for the exporter tests
+
+use std::rc::Rc;
+
+use glam::{Vec2, Vec3};
+
+use crate::types::{Disk, Mesh, MeshMeta};
+use crate::types::cooked::{BodySegment, DiskInformation};
+
+/// Triangular tube between two rings, with a flat cap over the first.
+/// The cap belongs to segment 0 and the band to segment 1.
+pub fn two_ring_mesh() -> Mesh {
+    let mut mesh = Mesh::default();
+    let profile = [(1f32, 0f32), (0f32, 1f32), (-1f32, 0f32)];
+    let mut rings = Vec::new();
+
+    for z in [0f32, 1f32] {
+        let ring: Vec<_> = profile.iter()
+            .map(|&(x, y)| Vec3::new(x, y, z))
+            .collect();
+        let uvs: Vec<_> = (0..=ring.len())
+            .map(|idx| Vec2::new(idx as f32 / 3f32, z))
+            .collect();
+
+        rings.push((mesh.add_ring(&ring), mesh.add_uvs(&uvs)));
+    }
+
+    let meta = |body_idx: usize, cap| MeshMeta {
+        body_idx,
+        disk_info_idx: body_idx,
+        color: Some(body_idx as u32 + 1),
+        cap,
+        branch: 0,
+    };
+
+    let cap: Vec<_> = rings[0].0.iter().rev().copied().collect();
+    mesh.add_face(cap, Vec::new(), Some(meta(0, true)));
+    mesh.add_band(
+        (&rings[0].0, &rings[0].1),
+        (&rings[1].0, &rings[1].1),
+        Some(meta(1, false)),
+    );
+
+    mesh
+}
+
+/// Segment 0 rising 2 units, with segment 1 turned a quarter about Z
+pub fn two_segment_body() -> BodySegment {
+    let segment = |index, action, value| BodySegment {
+        index,
+        disk_info: None,
+        action,
+        value,
+        color: None,
+        left: None,
+        right: None,
+    };
+
+    BodySegment {
+        left: Some(Box::new(segment(1, 4, 90f32))),
+        ..segment(0, 1, 2f32)
+    }
+}
+
+/// Chain of segments 0 to `len - 1`, each rising 1 unit and placing a
+/// square disk.  Segment `idx` has color `idx` and a disk information
+/// entry at position `idx` with id `10 + idx`.
+pub fn chain_body(len: usize) -> BodySegment {
+    let disk: Rc<Disk> = Rc::new(
+        [(1f32, 0f32), (0f32, 1f32), (-1f32, 0f32), (0f32, -1f32)]
+            .into_iter()
+            .map(|(x, y)| (Vec3::new(x, y, 0f32), 0))
+            .collect()
+    );
+
+    (0..len).rev().fold(None, |child, index| {
+        Some(BodySegment {
+            index,
+            disk_info: Some(Rc::new(DiskInformation {
+                index,
+                shift: Vec2::ZERO,
+                scale: Vec2::ONE,
+                disk: Some(Rc::clone(&disk)),
+                disk_idx: Some(0),
+                id: 10 + index as i32,
+                flags: 0,
+                arr1: [0f32; 4],
+                arr2: [0f32; 4],
+            })),
+            action: 1,
+            value: 1f32,
+            color: Some(index as u32),
+            left: child.map(Box::new),
+            right: None,
+        })
+    }).unwrap()
+}
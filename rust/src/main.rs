@@ -1,19 +1,62 @@
 use std::env;
 use std::iter;
 use std::fs::File;
-use std::io::BufReader;
-use std::rc::Rc;
-use glam::{Affine3, Mat3, Vec3};
+use std::io::{self, BufReader, BufWriter, Write};
+use std::path::{self, Component, Path};
 
 use iter::Iterator;
 
 mod parse;
 mod types;
 mod app_error;
+mod palette;
+mod cli;
+mod mdl;
+mod skeleton;
+mod smd;
+mod bvh;
+mod tree;
+mod usd;
+mod build;
+mod sink;
+mod select;
+mod union;
+mod coords;
+mod export;
+mod obj;
+mod vertex_map;
+mod view;
+mod centerline;
+mod blueprint;
+mod png;
+mod render;
+#[cfg(test)]
+mod fixtures;
 
 use parse::parse_model;
-use types::{Mesh, Disk, MeshMeta};
+use types::{Mesh, Model, Triangulation};
+use types::cooked::BodySegment;
 use app_error::AppError;
+use palette::{Palette, MaterialLibrary};
+use cli::{
+    Command, Options, BlueprintOptions, RenderOptions, TurntableOptions,
+    TreeOptions, Format,
+};
+use mdl::Mdl;
+use skeleton::Skeleton;
+use smd::Smd;
+use bvh::Bvh;
+use tree::{TextTree, DotTree};
+use usd::Usda;
+use build::{build_mesh, BuildOptions};
+use export::Exporter;
+use obj::Obj;
+use vertex_map::{VertexMap, VertexMapFormat};
+use centerline::{Centerline, CenterlineFile, CenterlineFormat};
+use sink::walk_body;
+use blueprint::Blueprint;
+use png::{Apng, Image, Png};
+use render::{Camera, Renderer, RenderSettings};
 
 
 /*
@@ -42,114 +85,406 @@ impl Display for Record {
 
 
 fn main() -> Result<(), AppError> {
-    let mut args = env::args_os();
-    let arg = args.nth(1);
+    match Command::parse(env::args_os().skip(1))? {
+        Command::Convert(options) => convert(*options),
+        Command::Blueprint(options) => blueprint(*options),
+        Command::Render(options) => render(*options),
+        Command::Turntable(options) => turntable(*options),
+        Command::Tree(options) => tree(options),
+    }
+}
 
-    let path = arg.ok_or(AppError("Provide argument for input path".into()))?;
+fn load_model(path: &Path) -> Result<Model, AppError> {
     let file = File::open(path).map_err(
         |err| AppError(err.to_string())
     )?;
 
     let reader = BufReader::new(file);
-    let model = parse_model(reader)?;
+    parse_model(reader)
+}
+
+/// Builds the mesh, warning about problems merging the tubes
+fn build(node: Option<&BodySegment>, options: &BuildOptions) -> Mesh {
+    let (mesh, union_report) = build_mesh(node, options);
+
+    if let Some(report) = union_report
+        && !report.is_clean()
+    {
+        eprint!("{report}");
+    }
+
+    mesh
+}
+
+fn load_palette(path: Option<&Path>) -> Result<Palette, AppError> {
+    let Some(path) = path else {
+        return Ok(Palette::default());
+    };
+
+    let file = File::open(path).map_err(
+        |err| AppError(err.to_string())
+    )?;
+
+    Palette::from_reader(BufReader::new(file))
+}
+
+fn write_output(
+    path: Option<&Path>,
+    exporter: &dyn Exporter,
+) -> Result<(), AppError> {
+    let writer: Box<dyn Write> = if let Some(path) = path {
+        Box::new(File::create(path).map_err(
+            |err| AppError(err.to_string())
+        )?)
+    } else {
+        Box::new(io::stdout().lock())
+    };
+
+    let mut writer = BufWriter::new(writer);
+
+    exporter.export(&mut writer)
+        .and_then(|_| writer.flush())
+        .map_err(|err| AppError(err.to_string()))
+}
+
+fn convert(mut options: Options) -> Result<(), AppError> {
+    let format = options.format();
+
+    if format.needs_triangles() {
+        options.build.triangulate.get_or_insert(Triangulation::Fixed);
+    }
+
+    let palette = load_palette(options.palette.as_deref())?;
+    let model = load_model(&options.input)?;
 
     let node = model.body();
+    let selection = &options.build.selection;
+    selection.check(node)?;
 
-    let mut mesh = Default::default();
-
-    walk_body(
-        node,
-        Affine3::IDENTITY,
-        &mut mesh,
-        None,
-        None
-    );
-
-    println!("{mesh}");
-
-    Ok(())
-}
-
-fn walk_body(
-    node: Option<&types::cooked::BodySegment>,
-    mut xform: Affine3,
-    mesh: &mut Mesh,
-    mut prev_disk: Option<Rc<Disk>>,
-    mut prev_xformd_disk: Option<Rc<Vec<Vec3>>>,
-) {
-    if let Some(segment) = node {
-        match segment.action {
-            0 => {
-                xform*= Affine3::from_translation(
-                    [0f32, 0f32, segment.value].into()
-                )
-            },
-            1 => {
-                xform*= Affine3::from_translation(
-                    [0f32, 0f32, segment.value].into()
+    let mesh = build(node, &options.build).transformed(&options.coords);
+    let skeleton = Skeleton::from_body(node, selection)
+        .transformed(&options.coords);
+
+    if let Some(path) = options.vertex_map.as_deref() {
+        // MDL splits vertices along color bands and the skin seam, and SMD
+        // lists every triangle corner
+        let order: Vec<_> = match format {
+            Format::Mdl => Mdl { mesh: &mesh, palette: &palette }
+                .vertex_order(),
+            Format::Smd => Smd { skeleton: &skeleton, mesh: Some(&mesh) }
+                .vertex_order(),
+            _ => (0..mesh.verts().len()).collect(),
+        };
+
+        let vertex_map = VertexMap {
+            mesh: &mesh,
+            order: &order,
+            format: VertexMapFormat::from_path(path),
+        };
+
+        write_output(Some(path), &vertex_map)?;
+    }
+
+    if let Some(path) = options.centerline.as_deref() {
+        let mut centerline = Centerline::default();
+        walk_body(node, selection, &mut centerline);
+
+        // Views are drawn in the model's own axes
+        let format = CenterlineFormat::from_path(path, options.view);
+
+        if !matches!(format, CenterlineFormat::Svg(_)) {
+            centerline = centerline.transformed(&options.coords);
+        }
+
+        write_output(Some(path), &CenterlineFile {
+            centerline: &centerline,
+            format,
+        })?;
+    }
+
+    let exporter: Box<dyn Exporter> = match format {
+        Format::Obj => Box::new(Obj {
+            mesh: &mesh,
+            mtllib: write_mtl(&mesh, &palette, &options)?,
+            grouping: &options.obj,
+        }),
+        Format::Mdl => Box::new(Mdl {
+            mesh: &mesh,
+            palette: &palette,
+        }),
+        Format::Smd => Box::new(Smd {
+            skeleton: &skeleton,
+            mesh: Some(&mesh),
+        }),
+        Format::SmdAnim => Box::new(Smd {
+            skeleton: &skeleton,
+            mesh: None,
+        }),
+        Format::Bvh => Box::new(Bvh::bind_pose(&skeleton)?),
+        Format::Usda => Box::new(Usda {
+            mesh: &mesh,
+            skeleton: &skeleton,
+            palette: &palette,
+            up: options.coords.up,
+        }),
+    };
+
+    write_output(options.output.as_deref(), exporter.as_ref())
+}
+
+fn blueprint(options: BlueprintOptions) -> Result<(), AppError> {
+    let palette = load_palette(options.palette.as_deref())?;
+    let model = load_model(&options.input)?;
+
+    let node = model.body();
+    options.build.selection.check(node)?;
+
+    let mesh = build(node, &options.build);
+    let ground = mesh.verts().iter()
+        .map(|v| v.z)
+        .fold(f32::INFINITY, f32::min);
+
+    // Top of the segment's disks, or where it sits if it has none
+    let withers = options.withers
+        .map(|body_idx| {
+            let mut centerline = Centerline::default();
+            walk_body(node, &options.build.selection, &mut centerline);
+
+            let origins = centerline.branches.iter()
+                .flat_map(|branch| branch.segments.iter().zip(&branch.points))
+                .filter(|&(&idx, _)| idx == body_idx)
+                .map(|(_, point)| point.z);
+
+            (0..mesh.verts().len())
+                .filter(|&idx| mesh.source(idx)
+                    .is_some_and(|s| s.body_idx == body_idx)
                 )
-            },
-            2 => {
-                xform*= Affine3::from_rotation_x(segment.value.to_radians())
-            },
-            3 => {
-                xform*= Affine3::from_rotation_y(segment.value.to_radians())
-            },
-            4 => {
-                xform*= Affine3::from_rotation_z(segment.value.to_radians())
-            },
+                .map(|idx| mesh.verts()[idx].z)
+                .reduce(f32::max)
+                .or(origins.reduce(f32::max))
+                .map(|z| z - ground)
+                .ok_or(AppError(format!(
+                    "Body segment {body_idx} is not in the blueprint"
+                )))
+        })
+        .transpose()?;
+
+    let blueprint = Blueprint {
+        mesh: &mesh,
+        palette: &palette,
+        views: &options.views,
+        silhouette: options.silhouette,
+        withers,
+    };
+
+    write_output(options.output.as_deref(), &blueprint)
+}
+
+fn render(options: RenderOptions) -> Result<(), AppError> {
+    let palette = load_palette(options.palette.as_deref())?;
+    let model = load_model(&options.input)?;
+
+    let node = model.body();
+    options.build.selection.check(node)?;
+
+    let mesh = build(node, &options.build);
+    let image = Renderer {
+        mesh: &mesh,
+        palette: &palette,
+        settings: &options.settings,
+    }.render();
+
+    write_output(options.output.as_deref(), &Png { image: &image })
+}
+
+fn turntable(options: TurntableOptions) -> Result<(), AppError> {
+    let palette = load_palette(options.palette.as_deref())?;
+    let model = load_model(&options.input)?;
+
+    let node = model.body();
+    options.build.selection.check(node)?;
+
+    let mesh = build(node, &options.build);
+    let frames = turntable_frames(&mesh, &palette, &options);
 
-            a => eprintln!("Unrecognized action {a}"),
+    if options.frame_path(0).is_some() {
+        for (frame, image) in frames.iter().enumerate() {
+            let path = options.frame_path(frame);
+            write_output(path.as_deref(), &Png { image })?;
         }
 
-        if let Some(disk_info) = segment.disk_info.as_ref() {
-            xform *= Affine3::from_translation(
-                (disk_info.shift, 0f32).into()
-            );
-
-            let disk: Option<Vec<_>> = disk_info.disk.as_ref()
-                .or(prev_disk.as_ref())
-                .map(
-                    |d| d.iter()
-                        .map(|&(mut v, _)| {
-                            v = Mat3::from_diagonal(
-                                (disk_info.scale, 1f32).into()
-                            ) * v;
-                            xform.transform_point3(v)
-                        })
-                        .collect()
-                );
-
-            if let Some(ref d) = disk {
-                if let Some(ref old_d) = prev_xformd_disk {
-                    mesh.add_loop(
-                        &old_d[..],
-                        &d[..],
-                        Some(MeshMeta {
-                            body_idx: segment.index,
-                            disk_info_idx: disk_info.index,
-                        })
-                    );
-                }
-
-                prev_xformd_disk = disk.map(Rc::new);
-                prev_disk = disk_info.disk.clone().or(prev_disk);
-            } 
+        Ok(())
+    } else {
+        write_output(options.output.as_deref(), &Apng {
+            frames: &frames,
+            fps: options.fps,
+        })
+    }
+}
+
+/// One full turn about the vertical axis, in even steps
+fn turntable_frames(
+    mesh: &Mesh,
+    palette: &Palette,
+    options: &TurntableOptions,
+) -> Vec<Image> {
+    let start = options.settings.camera;
+
+    (0..options.frames)
+        .map(|frame| {
+            let turn = 360f32 * frame as f32 / options.frames as f32;
+            let settings = RenderSettings {
+                camera: Camera { yaw: start.yaw + turn, ..start },
+                ..options.settings
+            };
+
+            Renderer { mesh, palette, settings: &settings }.render()
+        })
+        .collect()
+}
+
+fn tree(options: TreeOptions) -> Result<(), AppError> {
+    let model = load_model(&options.input)?;
+
+    let exporter: &dyn Exporter = if options.dot {
+        &DotTree { model: &model }
+    } else {
+        &TextTree { model: &model }
+    };
+
+    write_output(options.output.as_deref(), exporter)
+}
+
+/// Writes the material library if there is one, returning the name to
+/// reference it by
+fn write_mtl(
+    mesh: &Mesh,
+    palette: &Palette,
+    options: &Options,
+) -> Result<Option<String>, AppError> {
+    let Some(path) = options.mtl_path() else {
+        return Ok(None);
+    };
+
+    let library = MaterialLibrary {
+        palette,
+        colors: mesh.colors(),
+    };
+
+    write_output(Some(&path), &library)?;
+
+    // The OBJ looks for its library relative to its own directory
+    let mtllib = match options.output.as_deref() {
+        Some(output) => {
+            let dir = output.parent()
+                .filter(|dir| !dir.as_os_str().is_empty())
+                .unwrap_or(Path::new("."));
+            relative_path(dir, &path)?
+        },
+        None => path.to_string_lossy().into_owned(),
+    };
+
+    Ok(Some(mtllib))
+}
+
+/// Path to `target` from the directory `base`, with forward slashes
+fn relative_path(base: &Path, target: &Path) -> Result<String, AppError> {
+    // Components with `.` and `..` resolved, without following links
+    fn normalize(path: &Path) -> Vec<Component<'_>> {
+        let mut components = Vec::new();
+
+        for component in path.components() {
+            match component {
+                Component::CurDir => {},
+                Component::ParentDir
+                    if matches!(components.last(), Some(Component::Normal(_)))
+                => {
+                    components.pop();
+                },
+                _ => components.push(component),
+            }
         }
 
-        walk_body(
-            segment.left.as_deref(),
-            xform,
-            mesh,
-            prev_disk.clone(),
-            prev_xformd_disk.clone(),
-        );
-        walk_body(
-            segment.right.as_deref(),
-            xform,
-            mesh,
-            prev_disk,
-            prev_xformd_disk,
-        );
+        components
+    }
+
+    let absolute = |path: &Path| {
+        path::absolute(path).map_err(|err| AppError(err.to_string()))
+    };
+    let (base, target) = (absolute(base)?, absolute(target)?);
+    let (base, target) = (normalize(&base), normalize(&target));
+    let common = base.iter()
+        .zip(&target)
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    let parts: Vec<_> = iter::repeat_n("..".into(), base.len() - common)
+        .chain(target[common..].iter().map(|c| {
+            c.as_os_str().to_string_lossy().into_owned()
+        }))
+        .collect();
+
+    Ok(parts.join("/"))
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec3;
+
+    use super::*;
+
+    #[test]
+    fn material_library_relative_to_obj() {
+        let relative = |base: &str, target: &str| {
+            relative_path(Path::new(base), Path::new(target)).unwrap()
+        };
+
+        assert_eq!(relative(".", "x.mtl"), "x.mtl");
+        assert_eq!(relative(".", "sub/x.mtl"), "sub/x.mtl");
+        assert_eq!(relative("out", "sub/./x.mtl"), "../sub/x.mtl");
+        assert_eq!(relative("a/b", "a/c/../b/x.mtl"), "x.mtl");
+        assert_eq!(relative("/tmp/out", "/tmp/x.mtl"), "../x.mtl");
+    }
+
+    #[test]
+    fn turntable_turns_once() {
+        // Wall facing the side camera, seen edge on a quarter turn later
+        let mut mesh = Mesh::default();
+        let verts = mesh.add_ring(&[
+            Vec3::new(0f32, -1f32, 0f32),
+            Vec3::new(0f32, 1f32, 0f32),
+            Vec3::new(0f32, 1f32, 1f32),
+            Vec3::new(0f32, -1f32, 1f32),
+        ]);
+        mesh.add_face(verts, vec![], None);
+
+        let background = [10, 20, 30, 255];
+        let options = TurntableOptions {
+            settings: RenderSettings {
+                width: 32,
+                height: 32,
+                background,
+                ..RenderSettings::default()
+            },
+            frames: 4,
+            ..TurntableOptions::default()
+        };
+        let frames = turntable_frames(&mesh, &Palette::default(), &options);
+        let blank = |image: &Image| {
+            image.pixels.iter().all(|&pixel| pixel == background)
+        };
+
+        assert_eq!(frames.len(), 4);
+        assert!(!blank(&frames[0]) && !blank(&frames[2]));
+        assert!(blank(&frames[1]) && blank(&frames[3]));
+
+        // Numbered as wide as the last frame's number
+        let options = TurntableOptions {
+            output: Some("spin_{frame}.png".into()),
+            frames: 12,
+            ..options
+        };
+        assert_eq!(options.frame_path(3), Some("spin_03.png".into()));
+        assert_eq!(options.frame_path(11), Some("spin_11.png".into()));
     }
 }
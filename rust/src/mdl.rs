@@ -0,0 +1,437 @@
+use std::f32::consts::TAU;
+use std::io::{self, Write};
+use std::collections::HashMap;
+
+use glam::Vec3;
+
+use crate::types::{fan, Mesh};
+use crate::palette::{Palette, Rgb};
+use crate::export::Exporter;
+
+mod anorms;
+
+const IDENT: &[u8; 4] = b"IDPO";
+const VERSION: i32 = 6;
+const SKIN_WIDTH: usize = 128;
+const BAND_HEIGHT: usize = 32;
+const FRAME_NAME: &[u8] = b"base";
+
+// Indices from 224 up are fullbright and would glow in-game
+const QUAKE_PALETTE: &[u8; 768] = include_bytes!("mdl/palette.lmp");
+const SKIN_COLOR_CT: usize = 224;
+
+/// Skin vertex: mesh vertex, color band, and whether the cylindrical
+/// mapping wrapped around
+type SkinVertKey = (usize, usize, bool);
+
+struct Triangle {
+    verts: [usize; 3],
+}
+
+struct SkinVert {
+    vert: usize,
+    s: i32,
+    t: i32,
+}
+
+/// Single-frame Quake 1 model.  Faces are split into triangles, each color
+/// gets a flat horizontal band in the generated skin, and texture
+/// coordinates are projected cylindrically around the Z axis.
+pub struct Mdl<'a> {
+    pub mesh: &'a Mesh,
+    pub palette: &'a Palette,
+}
+
+impl Mdl<'_> {
+    /// Mesh vertex behind each vertex of the model, in the order written
+    pub fn vertex_order(&self) -> Vec<usize> {
+        Skin::new(self.mesh).verts.iter()
+            .map(|skin_vert| skin_vert.vert)
+            .collect()
+    }
+}
+
+impl Exporter for Mdl<'_> {
+    fn export(&self, writer: &mut dyn Write) -> io::Result<()> {
+        write_mdl(self.mesh, self.palette, writer)
+    }
+}
+
+/// Triangles laid out on the skin, one band per color.  A mesh vertex
+/// becomes one skin vertex per band it is used in, and another where
+/// triangles wrap across the seam.
+struct Skin {
+    colors: Vec<Option<u32>>,
+    verts: Vec<SkinVert>,
+    triangles: Vec<Triangle>,
+    total_area: f32,
+}
+
+impl Skin {
+    fn new(mesh: &Mesh) -> Self {
+        let verts = mesh.verts();
+        let colors: Vec<_> = mesh.colors().into_iter().collect();
+        let (mins, maxs) = mesh.bounds().unwrap_or_default();
+
+        let center = (mins + maxs) / 2f32;
+        let height = (maxs.z - mins.z).max(f32::EPSILON);
+
+        let cylinder_u = |v: Vec3| {
+            let offset = v - center;
+            offset.y.atan2(offset.x) / TAU + 0.5f32
+        };
+
+        let mut skin_verts = Vec::<SkinVert>::new();
+        let mut skin_vert_map = HashMap::<SkinVertKey, usize>::new();
+        let mut triangles = Vec::<Triangle>::new();
+        let mut total_area = 0f32;
+
+        for (face, meta) in mesh.faces() {
+            let color = meta.and_then(|m| m.color);
+            let band = colors.iter()
+                .position(|&c| c == color)
+                .unwrap_or(0);
+
+            // Quake treats clockwise triangles as front-facing
+            for [idx1, idx2, idx3] in fan(face) {
+                let tri = [idx1, idx3, idx2];
+                let [a, b, c] = tri.map(|idx| verts[idx]);
+                total_area+= (b - a).cross(c - a).length() / 2f32;
+
+                let us = tri.map(|idx| cylinder_u(verts[idx]));
+                let min_u = us.iter().copied().fold(f32::INFINITY, f32::min);
+                let max_u = us.iter().copied()
+                    .fold(f32::NEG_INFINITY, f32::max);
+                let crosses_seam = max_u - min_u > 0.5f32;
+
+                let tri_verts = [0, 1, 2].map(|corner| {
+                    let vert = tri[corner];
+                    let wrapped = crosses_seam && us[corner] < 0.5f32;
+                    let key = (vert, band, wrapped);
+
+                    // Vertices pulled across the seam are pinned to the
+                    // right edge rather than overrunning the skin
+                    *skin_vert_map.entry(key).or_insert_with(|| {
+                        let u = if wrapped { 1f32 } else { us[corner] };
+                        let v = (maxs.z - verts[vert].z) / height;
+                        let s = u * (SKIN_WIDTH - 1) as f32;
+                        let t = (band * BAND_HEIGHT) as f32 + 1f32
+                            + v * (BAND_HEIGHT - 3) as f32;
+
+                        skin_verts.push(SkinVert {
+                            vert,
+                            s: s.round() as i32,
+                            t: t.round() as i32,
+                        });
+
+                        skin_verts.len() - 1
+                    })
+                });
+
+                triangles.push(Triangle { verts: tri_verts });
+            }
+        }
+
+        Self {
+            colors,
+            verts: skin_verts,
+            triangles,
+            total_area,
+        }
+    }
+}
+
+fn write_mdl(
+    mesh: &Mesh,
+    palette: &Palette,
+    writer: &mut dyn Write,
+) -> io::Result<()> {
+    let verts = mesh.verts();
+    let Skin { colors, verts: skin_verts, triangles, total_area } =
+        Skin::new(mesh);
+    let band_ct = colors.len().max(1);
+    let skin_height = band_ct * BAND_HEIGHT;
+
+    let (mins, maxs) = mesh.bounds().unwrap_or_default();
+    let scale = ((maxs - mins) / 255f32).max(Vec3::splat(f32::EPSILON));
+    let normals = mesh.vertex_normals();
+
+    let radius = verts.iter()
+        .map(|v| v.length())
+        .fold(0f32, f32::max);
+
+    let size = if triangles.is_empty() {
+        0f32
+    } else {
+        total_area / triangles.len() as f32
+    };
+
+    let quantize = |v: Vec3| {
+        let [x, y, z] = ((v - mins) / scale).round().into();
+        [x, y, z].map(|c| c.clamp(0f32, 255f32) as u8)
+    };
+
+    // Header
+    writer.write_all(IDENT)?;
+    write_i32(writer, VERSION)?;
+    write_vec3(writer, scale)?;
+    write_vec3(writer, mins)?;
+    write_f32(writer, radius)?;
+    write_vec3(writer, Vec3::ZERO)?;
+    write_i32(writer, 1)?;
+    write_i32(writer, SKIN_WIDTH as i32)?;
+    write_i32(writer, skin_height as i32)?;
+    write_i32(writer, skin_verts.len() as i32)?;
+    write_i32(writer, triangles.len() as i32)?;
+    write_i32(writer, 1)?;
+    write_i32(writer, 0)?;
+    write_i32(writer, 0)?;
+    write_f32(writer, size)?;
+
+    // Skin
+    write_i32(writer, 0)?;
+
+    for band in 0..band_ct {
+        let color = colors.get(band).copied().flatten();
+        let index = nearest_palette_index(palette.rgb(color));
+        let row = [index; SKIN_WIDTH];
+
+        for _ in 0..BAND_HEIGHT {
+            writer.write_all(&row)?;
+        }
+    }
+
+    for skin_vert in &skin_verts {
+        write_i32(writer, 0)?;
+        write_i32(writer, skin_vert.s)?;
+        write_i32(writer, skin_vert.t)?;
+    }
+
+    for triangle in &triangles {
+        write_i32(writer, 1)?;
+
+        for &vert in &triangle.verts {
+            write_i32(writer, vert as i32)?;
+        }
+    }
+
+    // Frame
+    write_i32(writer, 0)?;
+    writer.write_all(&quantize(mins))?;
+    writer.write_all(&[0])?;
+    writer.write_all(&quantize(maxs))?;
+    writer.write_all(&[0])?;
+
+    let mut name = [0u8; 16];
+    name[..FRAME_NAME.len()].copy_from_slice(FRAME_NAME);
+    writer.write_all(&name)?;
+
+    for skin_vert in &skin_verts {
+        writer.write_all(&quantize(verts[skin_vert.vert]))?;
+        writer.write_all(&[nearest_normal_index(normals[skin_vert.vert])])?;
+    }
+
+    Ok(())
+}
+
+fn nearest_normal_index(normal: Vec3) -> u8 {
+    anorms::NORMALS.iter()
+        .enumerate()
+        .max_by(|(_, a), (_, b)| {
+            normal.dot((**a).into()).total_cmp(&normal.dot((**b).into()))
+        })
+        .map(|(idx, _)| idx as u8)
+        .unwrap_or(0)
+}
+
+fn nearest_palette_index(rgb: Rgb) -> u8 {
+    let distance = |entry: &[u8]| -> i32 {
+        entry.iter()
+            .zip(rgb)
+            .map(|(&a, b)| (a as i32 - b as i32).pow(2))
+            .sum()
+    };
+
+    QUAKE_PALETTE.as_chunks::<3>().0[..SKIN_COLOR_CT].iter()
+        .enumerate()
+        .min_by_key(|(_, entry)| distance(&entry[..]))
+        .map(|(idx, _)| idx as u8)
+        .unwrap_or(0)
+}
+
+fn write_i32(writer: &mut dyn Write, value: i32) -> io::Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+fn write_f32(writer: &mut dyn Write, value: f32) -> io::Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+fn write_vec3(writer: &mut dyn Write, value: Vec3) -> io::Result<()> {
+    for component in value.to_array() {
+        write_f32(writer, component)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec2;
+
+    use crate::types::MeshMeta;
+
+    use super::*;
+
+    const HEADER_SIZE: usize = 84;
+
+    /// Square tube of three rings, with a different color between each
+    /// pair
+    fn tube() -> Mesh {
+        let mut mesh = Mesh::default();
+        let square = [(1f32, 0f32), (0f32, 1f32), (-1f32, 0f32), (0f32, -1f32)];
+        let mut rings = Vec::new();
+
+        for z in [0f32, 1f32, 2f32] {
+            let ring: Vec<_> = square.iter()
+                .map(|&(x, y)| Vec3::new(x, y, z))
+                .collect();
+            let uvs: Vec<_> = (0..=ring.len())
+                .map(|idx| Vec2::new(idx as f32 / 4f32, z / 2f32))
+                .collect();
+
+            rings.push((mesh.add_ring(&ring), mesh.add_uvs(&uvs)));
+        }
+
+        for (idx, pair) in rings.windows(2).enumerate() {
+            let meta = MeshMeta {
+                body_idx: idx,
+                disk_info_idx: idx,
+                color: Some(idx as u32 + 1),
+                cap: false,
+                branch: 0,
+            };
+
+            mesh.add_band(
+                (&pair[0].0, &pair[0].1),
+                (&pair[1].0, &pair[1].1),
+                Some(meta),
+            );
+        }
+
+        mesh
+    }
+
+    fn read_i32(bytes: &[u8], offset: usize) -> i32 {
+        i32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+    }
+
+    fn read_vec3(bytes: &[u8], offset: usize) -> Vec3 {
+        Vec3::from_array([0, 4, 8].map(|idx| {
+            f32::from_le_bytes(
+                bytes[offset + idx..offset + idx + 4].try_into().unwrap()
+            )
+        }))
+    }
+
+    #[test]
+    fn reads_back() {
+        let mesh = tube();
+        let palette = Palette::default();
+        let mut bytes = Vec::new();
+        write_mdl(&mesh, &palette, &mut bytes).unwrap();
+
+        assert_eq!(&bytes[..4], IDENT);
+        assert_eq!(read_i32(&bytes, 4), VERSION);
+
+        let skin_width = read_i32(&bytes, 52) as usize;
+        let skin_height = read_i32(&bytes, 56) as usize;
+        let vert_ct = read_i32(&bytes, 60) as usize;
+        let tri_ct = read_i32(&bytes, 64) as usize;
+
+        assert_eq!(read_i32(&bytes, 48), 1);
+        assert_eq!(read_i32(&bytes, 68), 1);
+        assert_eq!(skin_width, SKIN_WIDTH);
+        assert_eq!(skin_height, 2 * BAND_HEIGHT);
+        assert_eq!(tri_ct, 16);
+        assert!(vert_ct >= mesh.verts().len());
+
+        let skin_start = HEADER_SIZE + 4;
+        let skin_verts_start = skin_start + skin_width * skin_height;
+        let tris_start = skin_verts_start + vert_ct * 12;
+        let frame_start = tris_start + tri_ct * 16;
+        let frame_size = 4 + 8 + 16 + vert_ct * 4;
+
+        assert_eq!(bytes.len(), frame_start + frame_size);
+        assert_eq!(read_i32(&bytes, HEADER_SIZE), 0);
+        assert_eq!(read_i32(&bytes, frame_start), 0);
+        assert_eq!(&bytes[frame_start + 12..][..4], FRAME_NAME);
+
+        for idx in 0..vert_ct {
+            let offset = skin_verts_start + idx * 12;
+            let s = read_i32(&bytes, offset + 4);
+            let t = read_i32(&bytes, offset + 8);
+
+            assert_eq!(read_i32(&bytes, offset), 0);
+            assert!((0..skin_width as i32).contains(&s), "s = {s}");
+            assert!((0..skin_height as i32).contains(&t), "t = {t}");
+        }
+
+        for idx in 0..tri_ct {
+            let offset = tris_start + idx * 16;
+
+            assert_eq!(read_i32(&bytes, offset), 1);
+
+            for corner in 0..3 {
+                let vert = read_i32(&bytes, offset + 4 + corner * 4);
+                assert!((0..vert_ct as i32).contains(&vert));
+            }
+        }
+
+        // Frame vertices scaled back from bytes land on the mesh's
+        let scale = read_vec3(&bytes, 8);
+        let origin = read_vec3(&bytes, 20);
+        let order = Mdl { mesh: &mesh, palette: &palette }.vertex_order();
+        let verts_start = frame_start + 4 + 8 + 16;
+
+        for (idx, &vert) in order.iter().enumerate() {
+            let packed = &bytes[verts_start + idx * 4..][..3];
+            let decoded = origin + scale
+                * Vec3::from_array([0, 1, 2].map(|c| packed[c] as f32));
+            let error = (decoded - mesh.verts()[vert]).abs();
+
+            assert!(
+                error.cmple(scale).all(),
+                "vertex {idx} read back as {decoded}",
+            );
+        }
+    }
+
+    #[test]
+    fn vertex_order() {
+        let mesh = tube();
+        let palette = Palette::default();
+        let mut bytes = Vec::new();
+        write_mdl(&mesh, &palette, &mut bytes).unwrap();
+
+        let order = Mdl { mesh: &mesh, palette: &palette }.vertex_order();
+
+        assert_eq!(order.len(), read_i32(&bytes, 60) as usize);
+        assert!(order.len() > mesh.verts().len());
+        assert!((0..mesh.verts().len()).all(|idx| order.contains(&idx)));
+    }
+
+    #[test]
+    fn empty_mesh() {
+        let mut bytes = Vec::new();
+        write_mdl(&Mesh::default(), &Palette::default(), &mut bytes)
+            .unwrap();
+
+        assert_eq!(read_i32(&bytes, 60), 0);
+        assert_eq!(read_i32(&bytes, 64), 0);
+        assert_eq!(
+            bytes.len(),
+            HEADER_SIZE + 4 + SKIN_WIDTH * BAND_HEIGHT + 4 + 8 + 16,
+        );
+    }
+}
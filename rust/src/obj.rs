@@ -0,0 +1,315 @@
+use std::io::{self, Write};
+
+use crate::types::{Mesh, MeshMeta};
+use crate::palette::material_name;
+use crate::export::Exporter;
+
+/// What faces are gathered into an object or group by
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Grouping {
+    /// The faces between two consecutive rings, or of one cap
+    Loop,
+    /// The loops of a chain between two branch points
+    Branch,
+    /// The loops sharing a segment color
+    Color,
+    None,
+}
+
+impl Grouping {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "loop" => Some(Self::Loop),
+            "branch" => Some(Self::Branch),
+            "color" => Some(Self::Color),
+            "none" => Some(Self::None),
+            _ => None,
+        }
+    }
+
+    /// Faces with equal keys belong together
+    fn key(self, loop_idx: usize, meta: Option<&MeshMeta>) -> Option<usize> {
+        match self {
+            Self::Loop => Some(loop_idx),
+            Self::Branch => meta.map(|m| m.branch),
+            Self::Color => meta.and_then(|m| m.color).map(|c| c as usize),
+            Self::None => None,
+        }
+    }
+
+    fn default_name(self) -> &'static str {
+        match self {
+            Self::Loop => "body_{body}_disk_info_{disk_info}",
+            Self::Branch => "branch_{branch}",
+            Self::Color => "color_{color}",
+            Self::None => "",
+        }
+    }
+}
+
+/// How faces are split into `o` objects and `g` groups.  Names are
+/// templates where `{body}`, `{disk_info}`, `{color}` and `{branch}` are
+/// replaced by the values of the first loop in the object or group.
+#[derive(Clone, Debug)]
+pub struct ObjGrouping {
+    pub objects: Grouping,
+    pub object_name: Option<String>,
+    pub groups: Grouping,
+    /// Groups per loop are named after their metadata without a template
+    pub group_name: Option<String>,
+}
+
+impl Default for ObjGrouping {
+    fn default() -> Self {
+        Self {
+            objects: Grouping::None,
+            object_name: None,
+            groups: Grouping::Loop,
+            group_name: None,
+        }
+    }
+}
+
+/// Wavefront OBJ, with a material per segment loop
+pub struct Obj<'a> {
+    pub mesh: &'a Mesh,
+    /// Material library to reference, relative to the OBJ file
+    pub mtllib: Option<String>,
+    pub grouping: &'a ObjGrouping,
+}
+
+impl Obj<'_> {
+    /// Face indices ordered so that each object, and each group within it,
+    /// is written in one run
+    fn face_order(&self) -> Vec<(usize, Option<usize>, Option<usize>)> {
+        let grouping = self.grouping;
+        let mut loop_idx = 0;
+
+        let mut order: Vec<_> = self.mesh.faces()
+            .enumerate()
+            .map(|(face_idx, (_, meta))| {
+                if face_idx > 0 && self.mesh.group_start(face_idx).is_some() {
+                    loop_idx+= 1;
+                }
+
+                (
+                    face_idx,
+                    grouping.objects.key(loop_idx, meta),
+                    grouping.groups.key(loop_idx, meta),
+                )
+            })
+            .collect();
+
+        order.sort_by_key(|&(_, object, group)| (object, group));
+        order
+    }
+}
+
+impl Exporter for Obj<'_> {
+    fn export(&self, writer: &mut dyn Write) -> io::Result<()> {
+        let mesh = self.mesh;
+        let grouping = self.grouping;
+        let normals = mesh.corner_normals();
+        let faces: Vec<_> = mesh.faces().collect();
+
+        if let Some(mtllib) = &self.mtllib {
+            writeln!(writer, "mtllib {mtllib}")?;
+        }
+
+        for v in mesh.verts() {
+            let [x, y, z] = (*v).into();
+            writeln!(writer, "v {x:.9} {y:.9} {z:.9}")?;
+        }
+
+        for uv in mesh.uvs() {
+            writeln!(writer, "vt {:.6} {:.6}", uv.x, uv.y)?;
+        }
+
+        for n in &normals.normals[..] {
+            let [x, y, z] = (*n).into();
+            writeln!(writer, "vn {x:.6} {y:.6} {z:.6}")?;
+        }
+
+        let mut current = None;
+
+        for (face_idx, object, group) in self.face_order() {
+            let (face, meta) = faces[face_idx];
+            let new_object = current.is_none_or(|(o, _)| o != object);
+            let new_group = new_object
+                || current.is_some_and(|(_, g)| g != group);
+            current = Some((object, group));
+
+            if let Some(meta) = meta
+                && new_object
+                && grouping.objects != Grouping::None
+            {
+                let template = grouping.object_name.as_deref()
+                    .unwrap_or(grouping.objects.default_name());
+                writeln!(writer, "o {}", expand(template, meta))?;
+            }
+
+            if let Some(meta) = meta
+                && new_group
+                && grouping.groups != Grouping::None
+            {
+                match grouping.group_name.as_deref() {
+                    None if grouping.groups == Grouping::Loop => {
+                        writeln!(writer, "{meta}")?;
+                    },
+                    template => {
+                        let template = template
+                            .unwrap_or(grouping.groups.default_name());
+                        writeln!(writer, "g {}", expand(template, meta))?;
+                    },
+                }
+            }
+
+            if let Some(meta) = mesh.group_start(face_idx) {
+                writeln!(writer, "usemtl {}", material_name(meta.color))?;
+            }
+
+            let uvs = mesh.face_uvs(face_idx);
+
+            write!(writer, "f")?;
+
+            for (corner, (idx, normal_idx)) in face.iter()
+                .zip(&normals.faces[face_idx])
+                .enumerate()
+            {
+                let uv = uvs.get(corner)
+                    .map(|uv_idx| (uv_idx + 1).to_string())
+                    .unwrap_or_default();
+                write!(writer, " {}/{uv}/{}", idx + 1, normal_idx + 1)?;
+            }
+
+            writeln!(writer)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn expand(template: &str, meta: &MeshMeta) -> String {
+    let color = meta.color
+        .map(|c| c.to_string())
+        .unwrap_or("none".into());
+
+    template
+        .replace("{body}", &meta.body_idx.to_string())
+        .replace("{disk_info}", &meta.disk_info_idx.to_string())
+        .replace("{color}", &color)
+        .replace("{branch}", &meta.branch.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec3;
+
+    use super::*;
+
+    #[test]
+    fn materials() {
+        let mut mesh = Mesh::default();
+        let verts = mesh.add_ring(&[Vec3::X, Vec3::Y, Vec3::Z, Vec3::ONE]);
+        let meta = |color| Some(MeshMeta {
+            body_idx: 0,
+            disk_info_idx: 0,
+            color,
+            cap: false,
+            branch: 0,
+        });
+        mesh.add_face(verts[..3].to_vec(), Vec::new(), meta(Some(3)));
+        mesh.add_face(verts[1..].to_vec(), Vec::new(), None);
+        mesh.add_face(verts[..3].to_vec(), Vec::new(), meta(None));
+
+        let mut out = Vec::new();
+        Obj {
+            mesh: &mesh,
+            mtllib: Some("sub/horse.mtl".into()),
+            grouping: &ObjGrouping::default(),
+        }.export(&mut out).unwrap();
+
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<_> = out.lines()
+            .filter(|line| !line.starts_with('v'))
+            .collect();
+
+        assert_eq!(lines, [
+            "mtllib sub/horse.mtl",
+            "g body_idx=0 disk_info_idx=0",
+            "usemtl color_3",
+            "f 1//1 2//2 3//3",
+            "f 2//2 3//3 4//4",
+            "g body_idx=0 disk_info_idx=0",
+            "usemtl color_none",
+            "f 1//1 2//2 3//3",
+        ]);
+    }
+
+    #[test]
+    fn grouping_templates() {
+        let mut mesh = Mesh::default();
+        let verts = mesh.add_ring(&[Vec3::X, Vec3::Y, Vec3::Z]);
+        let groups = [(0, 1, Some(1), 0), (1, 5, Some(2), 1), (2, 6, None, 0)];
+
+        for (body_idx, disk_info_idx, color, branch) in groups {
+            let meta = MeshMeta {
+                body_idx,
+                disk_info_idx,
+                color,
+                cap: false,
+                branch,
+            };
+            mesh.add_face(verts.clone(), Vec::new(), Some(meta));
+        }
+
+        let names = |grouping: ObjGrouping| {
+            let mut out = Vec::new();
+            Obj { mesh: &mesh, mtllib: None, grouping: &grouping }
+                .export(&mut out)
+                .unwrap();
+
+            String::from_utf8(out).unwrap()
+                .lines()
+                .filter(|line| line.starts_with("o ") || line.starts_with("g "))
+                .map(String::from)
+                .collect::<Vec<_>>()
+        };
+
+        // Objects per branch, each holding a group per color, sorted with
+        // faces without a color first
+        assert_eq!(names(ObjGrouping {
+            objects: Grouping::Branch,
+            object_name: Some("part_{branch}_{color}".into()),
+            groups: Grouping::Color,
+            group_name: None,
+        }), [
+            "o part_0_none",
+            "g color_none",
+            "g color_1",
+            "o part_1_2",
+            "g color_2",
+        ]);
+
+        // Default object names per loop, and a group template overriding
+        // the loop metadata
+        assert_eq!(names(ObjGrouping {
+            objects: Grouping::Loop,
+            object_name: None,
+            groups: Grouping::Loop,
+            group_name: Some("{body}/{disk_info}".into()),
+        }), [
+            "o body_0_disk_info_1",
+            "g 0/1",
+            "o body_1_disk_info_5",
+            "g 1/5",
+            "o body_2_disk_info_6",
+            "g 2/6",
+        ]);
+
+        assert!(names(ObjGrouping {
+            groups: Grouping::None,
+            ..ObjGrouping::default()
+        }).is_empty());
+    }
+}
@@ -0,0 +1,137 @@
+use std::fmt;
+use std::io::{self, BufRead, Write};
+use std::str::FromStr;
+use std::collections::BTreeSet;
+
+use glam::Vec3;
+
+use fmt::{Formatter, Display};
+use crate::app_error::AppError;
+use crate::export::Exporter;
+
+pub type Rgb = [u8; 3];
+
+/// Placeholder colors for when no palette file is given.  The original
+/// program's palette is not known, so these are only picked to look like a
+/// horse; pass `--palette` for the real colors.
+const DEFAULT_COLORS: [Rgb; 16] = [
+    [0x8b, 0x5a, 0x2b], // bay
+    [0x3b, 0x24, 0x14], // dark brown
+    [0xf2, 0xee, 0xe4], // white marking
+    [0x1c, 0x1a, 0x18], // black
+    [0xa8, 0x5a, 0x24], // chestnut
+    [0xc8, 0xa0, 0x64], // dun
+    [0x8c, 0x8c, 0x8c], // grey
+    [0xe6, 0xd2, 0x9a], // palomino
+    [0x5c, 0x3c, 0x28], // liver
+    [0xd8, 0xc8, 0xb4], // cream
+    [0x60, 0x60, 0x64], // slate
+    [0xb4, 0x78, 0x78], // pink skin
+    [0x2a, 0x2a, 0x30], // eye
+    [0x78, 0x4c, 0x2c], // sorrel
+    [0xb0, 0xa8, 0xa0], // roan
+    [0x4a, 0x34, 0x22], // hoof
+];
+
+const FALLBACK_COLOR: Rgb = [0x80, 0x80, 0x80];
+
+/// Maps segment color indices to RGB colors
+#[derive(Clone, Debug)]
+pub struct Palette {
+    colors: Vec<Rgb>,
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self {
+            colors: DEFAULT_COLORS.to_vec(),
+        }
+    }
+}
+
+impl Palette {
+    /// Reads a palette with one "R G B" triple (0-255) per line, the line
+    /// order giving the color index.  Text after '#' is ignored, as are
+    /// blank lines.
+    pub fn from_reader(reader: impl BufRead) -> Result<Self, AppError> {
+        let mut colors = Vec::new();
+
+        for (line_idx, line) in reader.lines().enumerate() {
+            let line = line.map_err(|err| AppError(err.to_string()))?;
+            let line = line.split('#').next().unwrap_or("").trim();
+
+            if line.is_empty() {
+                continue;
+            }
+
+            let components: Vec<_> = line.split_whitespace()
+                .map(u8::from_str)
+                .collect();
+
+            if let [Ok(r), Ok(g), Ok(b)] = components[..] {
+                colors.push([r, g, b]);
+            } else {
+                return Err(AppError(format!(
+                    "Palette line {}: expected \"R G B\" with values 0-255",
+                    line_idx + 1,
+                )));
+            }
+        }
+
+        if colors.is_empty() {
+            return Err(AppError("Palette contains no colors".into()));
+        }
+
+        Ok(Self { colors })
+    }
+
+    pub fn rgb(&self, color: Option<u32>) -> Rgb {
+        color
+            .and_then(|c| usize::try_from(c).ok())
+            .and_then(|c| self.colors.get(c))
+            .copied()
+            .unwrap_or(FALLBACK_COLOR)
+    }
+
+    pub fn color(&self, color: Option<u32>) -> Vec3 {
+        let [r, g, b] = self.rgb(color);
+        Vec3::new(r as f32, g as f32, b as f32) / 255f32
+    }
+}
+
+pub fn material_name(color: Option<u32>) -> String {
+    match color {
+        Some(c) => format!("color_{c}"),
+        None => "color_none".to_string(),
+    }
+}
+
+/// Wavefront MTL file with one diffuse material per color index
+pub struct MaterialLibrary<'a> {
+    pub palette: &'a Palette,
+    pub colors: BTreeSet<Option<u32>>,
+}
+
+impl Display for MaterialLibrary<'_> {
+    fn fmt(&self, formatter: &mut Formatter) -> Result<(), fmt::Error> {
+        for &color in &self.colors {
+            let [r, g, b] = self.palette.color(color).into();
+
+            writeln!(formatter, "newmtl {}", material_name(color))?;
+            writeln!(formatter, "Ka 0.000000 0.000000 0.000000")?;
+            writeln!(formatter, "Kd {r:.6} {g:.6} {b:.6}")?;
+            writeln!(formatter, "Ks 0.000000 0.000000 0.000000")?;
+            writeln!(formatter, "d 1.000000")?;
+            writeln!(formatter, "illum 1")?;
+            writeln!(formatter)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Exporter for MaterialLibrary<'_> {
+    fn export(&self, writer: &mut dyn Write) -> io::Result<()> {
+        write!(writer, "{self}")
+    }
+}
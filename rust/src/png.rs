@@ -0,0 +1,656 @@
+use std::io::{self, Write};
+
+use crate::export::Exporter;
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a];
+
+/// Deflate window, and how far back matches are looked for
+const WINDOW: usize = 32768;
+/// Earlier positions with the same hash to try before giving up
+const MAX_CHAIN: usize = 64;
+const HASH_BITS: u32 = 15;
+
+const LENGTH_BASES: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59,
+    67, 83, 99, 115, 131, 163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4,
+    5, 5, 5, 5, 0,
+];
+const DISTANCE_BASES: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385,
+    513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10,
+    10, 11, 11, 12, 12, 13, 13,
+];
+
+const CRC_TABLE: [u32; 256] = crc_table();
+
+/// 8-bit RGBA pixels, row by row from the top left
+#[derive(Clone, Debug)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<[u8; 4]>,
+}
+
+impl Image {
+    pub fn new(width: usize, height: usize, fill: [u8; 4]) -> Self {
+        Self {
+            width,
+            height,
+            pixels: vec![fill; width * height],
+        }
+    }
+}
+
+/// Truecolor PNG with alpha
+pub struct Png<'a> {
+    pub image: &'a Image,
+}
+
+impl Exporter for Png<'_> {
+    fn export(&self, writer: &mut dyn Write) -> io::Result<()> {
+        writer.write_all(&SIGNATURE)?;
+        write_header(writer, self.image)?;
+        write_chunk(writer, b"IDAT", &zlib(&filter(self.image)))?;
+        write_chunk(writer, b"IEND", &[])
+    }
+}
+
+/// Animated PNG showing the frames in turn, looping forever.  Viewers
+/// without animation support show the first frame.
+pub struct Apng<'a> {
+    /// Frames of the same size
+    pub frames: &'a [Image],
+    pub fps: u16,
+}
+
+impl Exporter for Apng<'_> {
+    fn export(&self, writer: &mut dyn Write) -> io::Result<()> {
+        let Some(first) = self.frames.first() else {
+            return Ok(());
+        };
+
+        writer.write_all(&SIGNATURE)?;
+        write_header(writer, first)?;
+
+        // Frame count, then plays, where zero repeats forever
+        let mut control = Vec::with_capacity(8);
+        control.extend((self.frames.len() as u32).to_be_bytes());
+        control.extend(0u32.to_be_bytes());
+        write_chunk(writer, b"acTL", &control)?;
+
+        // Frame controls and data after the first share one numbering
+        let mut sequence = 0u32;
+
+        for (frame_idx, image) in self.frames.iter().enumerate() {
+            let mut control = Vec::with_capacity(26);
+            control.extend(sequence.to_be_bytes());
+            control.extend((image.width as u32).to_be_bytes());
+            control.extend((image.height as u32).to_be_bytes());
+            // Offset, then a delay of 1/fps seconds
+            control.extend([0u8; 8]);
+            control.extend(1u16.to_be_bytes());
+            control.extend(self.fps.to_be_bytes());
+            // Leave the area as is, and replace rather than blend over it
+            control.extend([0, 0]);
+            write_chunk(writer, b"fcTL", &control)?;
+            sequence+= 1;
+
+            let data = zlib(&filter(image));
+
+            if frame_idx == 0 {
+                write_chunk(writer, b"IDAT", &data)?;
+            } else {
+                let mut frame_data = Vec::with_capacity(data.len() + 4);
+                frame_data.extend(sequence.to_be_bytes());
+                frame_data.extend(data);
+                write_chunk(writer, b"fdAT", &frame_data)?;
+                sequence+= 1;
+            }
+        }
+
+        write_chunk(writer, b"IEND", &[])
+    }
+}
+
+fn write_header(writer: &mut dyn Write, image: &Image) -> io::Result<()> {
+    let mut data = Vec::with_capacity(13);
+    data.extend((image.width as u32).to_be_bytes());
+    data.extend((image.height as u32).to_be_bytes());
+    // Bit depth 8, RGBA, deflate, adaptive filtering, not interlaced
+    data.extend([8, 6, 0, 0, 0]);
+
+    write_chunk(writer, b"IHDR", &data)
+}
+
+fn write_chunk(
+    writer: &mut dyn Write,
+    kind: &[u8; 4],
+    data: &[u8],
+) -> io::Result<()> {
+    writer.write_all(&(data.len() as u32).to_be_bytes())?;
+    writer.write_all(kind)?;
+    writer.write_all(data)?;
+
+    let crc = crc32(crc32(!0, kind), data);
+    writer.write_all(&(!crc).to_be_bytes())
+}
+
+/// Scanlines, each behind the filter that leaves the smallest residuals
+fn filter(image: &Image) -> Vec<u8> {
+    let stride = image.width * 4;
+    let bytes: Vec<u8> = image.pixels.iter().flatten().copied().collect();
+    let mut out = Vec::with_capacity((stride + 1) * image.height);
+    let zeros = vec![0u8; stride];
+
+    for row in 0..image.height {
+        let line = &bytes[row * stride..][..stride];
+        let above = if row > 0 {
+            &bytes[(row - 1) * stride..][..stride]
+        } else {
+            &zeros[..]
+        };
+
+        let filtered = (0u8..5)
+            .map(|kind| {
+                let residuals: Vec<u8> = (0..stride)
+                    .map(|i| {
+                        let a = if i >= 4 { line[i - 4] } else { 0 };
+                        let b = above[i];
+                        let c = if i >= 4 { above[i - 4] } else { 0 };
+
+                        let predicted = match kind {
+                            0 => 0,
+                            1 => a,
+                            2 => b,
+                            3 => ((a as u16 + b as u16) / 2) as u8,
+                            _ => paeth(a, b, c),
+                        };
+
+                        line[i].wrapping_sub(predicted)
+                    })
+                    .collect();
+
+                (kind, residuals)
+            })
+            .min_by_key(|(_, residuals)| {
+                residuals.iter()
+                    .map(|&r| (r as i8).unsigned_abs() as usize)
+                    .sum::<usize>()
+            });
+
+        if let Some((kind, residuals)) = filtered {
+            out.push(kind);
+            out.extend(residuals);
+        }
+    }
+
+    out
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let pa = (p - a as i16).abs();
+    let pb = (p - b as i16).abs();
+    let pc = (p - c as i16).abs();
+
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+/// Zlib stream holding one deflate block with the fixed Huffman codes
+fn zlib(data: &[u8]) -> Vec<u8> {
+    let mut bits = BitWriter::default();
+    bits.bytes.extend([0x78, 0x01]);
+
+    // Final block, fixed codes
+    bits.write(1, 1);
+    bits.write(1, 2);
+
+    let mut heads = vec![usize::MAX; 1 << HASH_BITS];
+    let mut prev = vec![usize::MAX; data.len()];
+    let mut pos = 0;
+
+    while pos < data.len() {
+        let (length, distance) = longest_match(data, pos, &heads, &prev);
+
+        // Runs shorter than three bytes cost more than literals
+        let length = if length >= 3 {
+            bits.write_length(length);
+            bits.write_distance(distance);
+            length
+        } else {
+            bits.write_literal(data[pos] as u16);
+            1
+        };
+
+        for idx in pos..pos + length {
+            if idx + 3 <= data.len() {
+                let hash = hash(&data[idx..]);
+                prev[idx] = heads[hash];
+                heads[hash] = idx;
+            }
+        }
+
+        pos+= length;
+    }
+
+    bits.write_literal(256);
+    bits.flush();
+
+    let mut out = bits.bytes;
+    out.extend(adler32(data).to_be_bytes());
+    out
+}
+
+fn hash(bytes: &[u8]) -> usize {
+    let key = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], 0]);
+    (key.wrapping_mul(0x9e37_79b1) >> (32 - HASH_BITS)) as usize
+}
+
+/// Longest earlier run matching the bytes at `pos`, as length and distance
+fn longest_match(
+    data: &[u8],
+    pos: usize,
+    heads: &[usize],
+    prev: &[usize],
+) -> (usize, usize) {
+    if pos + 3 > data.len() {
+        return (0, 0);
+    }
+
+    let limit = (data.len() - pos).min(258);
+    let mut best = (0, 0);
+    let mut candidate = heads[hash(&data[pos..])];
+
+    for _ in 0..MAX_CHAIN {
+        if candidate == usize::MAX || pos - candidate > WINDOW {
+            break;
+        }
+
+        let length = (0..limit)
+            .take_while(|&i| data[candidate + i] == data[pos + i])
+            .count();
+
+        if length > best.0 {
+            best = (length, pos - candidate);
+
+            if length == limit {
+                break;
+            }
+        }
+
+        candidate = prev[candidate];
+    }
+
+    best
+}
+
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    buffer: u32,
+    count: u32,
+}
+
+impl BitWriter {
+    /// Appends the low `count` bits of `value`, least significant first
+    fn write(&mut self, value: u32, count: u32) {
+        self.buffer|= value << self.count;
+        self.count+= count;
+
+        while self.count >= 8 {
+            self.bytes.push(self.buffer as u8);
+            self.buffer>>= 8;
+            self.count-= 8;
+        }
+    }
+
+    /// Appends a Huffman code, which is packed most significant bit first
+    fn write_code(&mut self, code: u32, count: u32) {
+        self.write(code.reverse_bits() >> (32 - count), count);
+    }
+
+    fn write_literal(&mut self, value: u16) {
+        let value = value as u32;
+
+        match value {
+            0..=143 => self.write_code(0x30 + value, 8),
+            144..=255 => self.write_code(0x190 + value - 144, 9),
+            256..=279 => self.write_code(value - 256, 7),
+            _ => self.write_code(0xc0 + value - 280, 8),
+        }
+    }
+
+    fn write_length(&mut self, length: usize) {
+        let code = LENGTH_BASES.iter()
+            .rposition(|&base| base as usize <= length)
+            .unwrap_or(0);
+
+        self.write_literal(257 + code as u16);
+        self.write(
+            (length - LENGTH_BASES[code] as usize) as u32,
+            LENGTH_EXTRA[code] as u32,
+        );
+    }
+
+    fn write_distance(&mut self, distance: usize) {
+        let code = DISTANCE_BASES.iter()
+            .rposition(|&base| base as usize <= distance)
+            .unwrap_or(0);
+
+        self.write_code(code as u32, 5);
+        self.write(
+            (distance - DISTANCE_BASES[code] as usize) as u32,
+            DISTANCE_EXTRA[code] as u32,
+        );
+    }
+
+    fn flush(&mut self) {
+        if self.count > 0 {
+            self.write(0, 8 - self.count);
+        }
+    }
+}
+
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut idx = 0;
+
+    while idx < 256 {
+        let mut crc = idx as u32;
+        let mut bit = 0;
+
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                0xedb8_8320 ^ (crc >> 1)
+            } else {
+                crc >> 1
+            };
+            bit+= 1;
+        }
+
+        table[idx] = crc;
+        idx+= 1;
+    }
+
+    table
+}
+
+/// Continues a CRC-32 over `data`, without the final inversion
+fn crc32(crc: u32, data: &[u8]) -> u32 {
+    data.iter().fold(crc, |crc, &byte| {
+        CRC_TABLE[((crc ^ byte as u32) & 0xff) as usize] ^ (crc >> 8)
+    })
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (a, b) = data.iter().fold((1u32, 0u32), |(a, b), &byte| {
+        let a = (a + byte as u32) % 65521;
+        (a, (b + a) % 65521)
+    });
+
+    (b << 16) | a
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Reads a deflate stream least significant bit first
+    struct BitReader<'a> {
+        bytes: &'a [u8],
+        pos: usize,
+    }
+
+    impl BitReader<'_> {
+        fn bit(&mut self) -> u32 {
+            let bit = (self.bytes[self.pos / 8] >> (self.pos % 8)) & 1;
+            self.pos+= 1;
+            bit as u32
+        }
+
+        fn bits(&mut self, count: u8) -> usize {
+            (0..count).map(|idx| (self.bit() as usize) << idx).sum()
+        }
+
+        /// Huffman codes arrive most significant bit first
+        fn code(&mut self, count: u32) -> u32 {
+            (0..count).fold(0, |code, _| (code << 1) | self.bit())
+        }
+
+        fn symbol(&mut self) -> usize {
+            let code = self.code(7);
+
+            if code < 24 {
+                return 256 + code as usize;
+            }
+
+            let code = (code << 1) | self.bit();
+
+            match code {
+                0x30..=0xbf => (code - 0x30) as usize,
+                0xc0..=0xc7 => (280 + code - 0xc0) as usize,
+                _ => (144 + ((code << 1) | self.bit()) - 0x190) as usize,
+            }
+        }
+    }
+
+    /// Undoes `zlib`, which only ever writes one block of fixed codes
+    fn inflate(stream: &[u8]) -> Vec<u8> {
+        assert_eq!(&stream[..2], [0x78, 0x01]);
+
+        let mut reader = BitReader { bytes: &stream[2..], pos: 0 };
+        assert_eq!(reader.bits(1), 1);
+        assert_eq!(reader.bits(2), 1);
+
+        let mut out = Vec::new();
+
+        loop {
+            let symbol = reader.symbol();
+
+            match symbol {
+                0..=255 => out.push(symbol as u8),
+                256 => break,
+                _ => {
+                    let code = symbol - 257;
+                    let length = LENGTH_BASES[code] as usize
+                        + reader.bits(LENGTH_EXTRA[code]);
+                    let code = reader.code(5) as usize;
+                    let distance = DISTANCE_BASES[code] as usize
+                        + reader.bits(DISTANCE_EXTRA[code]);
+
+                    for _ in 0..length {
+                        out.push(out[out.len() - distance]);
+                    }
+                },
+            }
+        }
+
+        let end = 2 + reader.pos.div_ceil(8);
+        let checksum = stream[end..][..4].try_into().unwrap();
+        assert_eq!(u32::from_be_bytes(checksum), adler32(&out));
+        assert_eq!(stream.len(), end + 4);
+
+        out
+    }
+
+    /// Chunk types and contents, checking each CRC
+    fn chunks(bytes: &[u8]) -> Vec<([u8; 4], Vec<u8>)> {
+        assert_eq!(bytes[..8], SIGNATURE);
+
+        let mut chunks = Vec::new();
+        let mut pos = 8;
+
+        while pos < bytes.len() {
+            let word = |offset: usize| u32::from_be_bytes(
+                bytes[offset..offset + 4].try_into().unwrap()
+            );
+            let len = word(pos) as usize;
+            let kind: [u8; 4] = bytes[pos + 4..pos + 8].try_into().unwrap();
+            let data = &bytes[pos + 8..pos + 8 + len];
+
+            assert_eq!(word(pos + 8 + len), !crc32(crc32(!0, &kind), data));
+
+            chunks.push((kind, data.to_vec()));
+            pos+= 12 + len;
+        }
+
+        chunks
+    }
+
+    fn unfilter(data: &[u8], width: usize, height: usize) -> Vec<[u8; 4]> {
+        let stride = width * 4;
+        let mut bytes = vec![0u8; stride * height];
+
+        assert_eq!(data.len(), (stride + 1) * height);
+
+        for row in 0..height {
+            let kind = data[row * (stride + 1)];
+            let line = &data[row * (stride + 1) + 1..][..stride];
+
+            for i in 0..stride {
+                let a = if i >= 4 { bytes[row * stride + i - 4] } else { 0 };
+                let b = if row > 0 { bytes[(row - 1) * stride + i] } else { 0 };
+                let c = if row > 0 && i >= 4 {
+                    bytes[(row - 1) * stride + i - 4]
+                } else {
+                    0
+                };
+
+                let predicted = match kind {
+                    0 => 0,
+                    1 => a,
+                    2 => b,
+                    3 => ((a as u16 + b as u16) / 2) as u8,
+                    4 => paeth(a, b, c),
+                    _ => panic!("unknown filter {kind}"),
+                };
+
+                bytes[row * stride + i] = line[i].wrapping_add(predicted);
+            }
+        }
+
+        bytes.as_chunks::<4>().0.to_vec()
+    }
+
+    /// Gradients with repeats, noise and flat runs, to use every filter and
+    /// both literals and matches
+    fn test_image(width: usize, height: usize, seed: u32) -> Image {
+        let mut state = seed;
+        let mut image = Image::new(width, height, [0; 4]);
+
+        for y in 0..height {
+            for x in 0..width {
+                state = state.wrapping_mul(1_664_525)
+                    .wrapping_add(1_013_904_223);
+                let noise = (state >> 24) as u8;
+
+                image.pixels[y * width + x] = match (x / 8 + y / 8) % 3 {
+                    0 => [x as u8, y as u8, (x * y) as u8, 255],
+                    1 => [noise, noise / 2, 7, noise],
+                    _ => [40, 80, 120, 200],
+                };
+            }
+        }
+
+        image
+    }
+
+    #[test]
+    fn known_checksums() {
+        assert_eq!(!crc32(!0, b"IEND"), 0xae42_6082);
+        assert_eq!(!crc32(!0, b"123456789"), 0xcbf4_3926);
+        assert_eq!(adler32(b"Wikipedia"), 0x11e6_0398);
+        assert_eq!(adler32(b""), 1);
+    }
+
+    #[test]
+    fn deflate_round_trip() {
+        let mut long_run = vec![b'a'; 1000];
+        long_run.extend(b"abcabcabcabd".repeat(50));
+        long_run.extend((0..=255u8).cycle().take(70_000));
+
+        for data in [&b""[..], b"x", b"xyxyxyxy", &long_run] {
+            assert_eq!(inflate(&zlib(data)), data);
+        }
+    }
+
+    #[test]
+    fn png_round_trip() {
+        let image = test_image(37, 23, 1);
+        let mut bytes = Vec::new();
+        Png { image: &image }.export(&mut bytes).unwrap();
+
+        let chunks = chunks(&bytes);
+        let kinds: Vec<_> = chunks.iter().map(|(kind, _)| kind).collect();
+        assert_eq!(kinds, [b"IHDR", b"IDAT", b"IEND"]);
+
+        let header = &chunks[0].1;
+        assert_eq!(header[..8], [0, 0, 0, 37, 0, 0, 0, 23]);
+        assert_eq!(header[8..], [8, 6, 0, 0, 0]);
+
+        let data = inflate(&chunks[1].1);
+        assert_eq!(unfilter(&data, 37, 23), image.pixels);
+    }
+
+    #[test]
+    fn apng_frames() {
+        let frames: Vec<_> = (0..3)
+            .map(|seed| test_image(9, 5, seed))
+            .collect();
+        let mut bytes = Vec::new();
+        Apng { frames: &frames, fps: 12 }.export(&mut bytes).unwrap();
+
+        let chunks = chunks(&bytes);
+        let kinds: Vec<_> = chunks.iter().map(|(kind, _)| kind).collect();
+        assert_eq!(kinds, [
+            b"IHDR", b"acTL",
+            b"fcTL", b"IDAT",
+            b"fcTL", b"fdAT",
+            b"fcTL", b"fdAT",
+            b"IEND",
+        ]);
+        assert_eq!(chunks[1].1, [0, 0, 0, 3, 0, 0, 0, 0]);
+
+        let mut sequence = 0;
+        let mut frame_idx = 0;
+
+        for (kind, data) in &chunks[2..] {
+            let number = |data: &[u8]| {
+                u32::from_be_bytes(data[..4].try_into().unwrap())
+            };
+
+            let pixels = match kind {
+                b"fcTL" => {
+                    assert_eq!(number(data), sequence);
+                    assert_eq!(data[20..24], [0, 1, 0, 12]);
+                    sequence+= 1;
+                    continue;
+                },
+                b"IDAT" => unfilter(&inflate(data), 9, 5),
+                b"fdAT" => {
+                    assert_eq!(number(data), sequence);
+                    sequence+= 1;
+                    unfilter(&inflate(&data[4..]), 9, 5)
+                },
+                _ => continue,
+            };
+
+            assert_eq!(pixels, frames[frame_idx].pixels);
+            frame_idx+= 1;
+        }
+
+        assert_eq!(frame_idx, 3);
+    }
+}
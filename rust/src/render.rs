@@ -0,0 +1,343 @@
+use glam::{Vec2, Vec3};
+
+use crate::types::Mesh;
+use crate::palette::Palette;
+use crate::png::Image;
+use crate::view::View;
+
+/// Pixels rendered along each side of an output pixel, then averaged
+const SUPERSAMPLE: usize = 2;
+/// Room left around the model, relative to the image size
+const PADDING: f32 = 0.05;
+const AMBIENT: f32 = 0.25;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Shading {
+    /// One shade per face
+    Flat,
+    /// Shades lit at the corners and blended across each face
+    #[default]
+    Gouraud,
+}
+
+impl Shading {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "flat" => Some(Self::Flat),
+            "gouraud" => Some(Self::Gouraud),
+            _ => None,
+        }
+    }
+}
+
+/// Orthographic camera circling the model, in degrees.  At zero yaw and
+/// pitch it looks from the model's right, as in the side view; yaw turns
+/// it counter-clockwise about the vertical axis and pitch raises it.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Camera {
+    pub yaw: f32,
+    pub pitch: f32,
+}
+
+impl Camera {
+    pub fn from_view(view: View) -> Self {
+        match view {
+            View::Front => Self { yaw: 90f32, pitch: 0f32 },
+            View::Side => Self { yaw: 0f32, pitch: 0f32 },
+            View::Top => Self { yaw: -90f32, pitch: 90f32 },
+        }
+    }
+
+    /// Screen right, screen up and the direction towards the camera
+    fn basis(&self) -> [Vec3; 3] {
+        let (yaw_sin, yaw_cos) = self.yaw.to_radians().sin_cos();
+        let (pitch_sin, pitch_cos) = self.pitch.to_radians().sin_cos();
+
+        let toward = Vec3::new(
+            pitch_cos * yaw_cos,
+            pitch_cos * yaw_sin,
+            pitch_sin,
+        );
+        let right = Vec3::new(-yaw_sin, yaw_cos, 0f32);
+
+        [right, toward.cross(right), toward]
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RenderSettings {
+    pub camera: Camera,
+    pub shading: Shading,
+    pub width: usize,
+    pub height: usize,
+    /// Transparent unless given
+    pub background: [u8; 4],
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        Self {
+            camera: Camera::default(),
+            shading: Shading::default(),
+            width: 256,
+            height: 256,
+            background: [0; 4],
+        }
+    }
+}
+
+/// Rasterizes a mesh with a depth buffer, colored by segment and lit from
+/// over the camera's left shoulder.  The model is framed by the sphere
+/// around its bounding box, so it stays the same size from any angle.
+pub struct Renderer<'a> {
+    pub mesh: &'a Mesh,
+    pub palette: &'a Palette,
+    pub settings: &'a RenderSettings,
+}
+
+/// Corner of a triangle on the supersampled screen
+#[derive(Clone, Copy)]
+struct ScreenVertex {
+    pos: Vec2,
+    depth: f32,
+    color: Vec3,
+}
+
+impl Renderer<'_> {
+    pub fn render(&self) -> Image {
+        let settings = self.settings;
+        let width = settings.width * SUPERSAMPLE;
+        let height = settings.height * SUPERSAMPLE;
+        let mut colors = vec![None; width * height];
+        let mut depths = vec![f32::NEG_INFINITY; width * height];
+
+        let verts = self.mesh.verts();
+        let (mins, maxs) = self.mesh.bounds().unwrap_or_default();
+        let center = (mins + maxs) / 2f32;
+        let radius = ((maxs - mins).length() / 2f32).max(f32::EPSILON);
+        let scale = width.min(height) as f32
+            * (1f32 - 2f32 * PADDING) / (2f32 * radius);
+
+        let [right, up, toward] = settings.camera.basis();
+        let light = (toward * 0.8f32 + up * 0.5f32 - right * 0.3f32)
+            .normalize();
+        let normals = self.mesh.corner_normals();
+
+        for (face_idx, (face, meta)) in self.mesh.faces().enumerate() {
+            let base = self.palette.color(meta.and_then(|m| m.color));
+            let face_normal = self.mesh.face_normal(face).normalize_or_zero();
+            // Light both sides of open tubes
+            let side = face_normal.dot(toward).signum();
+            let shade = |normal: Vec3| {
+                let diffuse = (normal * side).dot(light).max(0f32);
+                base * (AMBIENT + (1f32 - AMBIENT) * diffuse)
+            };
+
+            let corners: Vec<_> = face.iter()
+                .zip(&normals.faces[face_idx])
+                .map(|(&idx, &normal_idx)| {
+                    let offset = verts[idx] - center;
+                    let normal = match settings.shading {
+                        Shading::Flat => face_normal,
+                        Shading::Gouraud => normals.normals[normal_idx],
+                    };
+
+                    ScreenVertex {
+                        pos: Vec2::new(
+                            width as f32 / 2f32 + offset.dot(right) * scale,
+                            height as f32 / 2f32 - offset.dot(up) * scale,
+                        ),
+                        depth: offset.dot(toward),
+                        color: shade(normal),
+                    }
+                })
+                .collect();
+
+            for i in 1..corners.len().saturating_sub(1) {
+                let triangle = [corners[0], corners[i], corners[i + 1]];
+                rasterize(
+                    triangle,
+                    width,
+                    height,
+                    &mut colors,
+                    &mut depths,
+                );
+            }
+        }
+
+        self.downsample(&colors)
+    }
+
+    /// Averages each block of samples into a pixel, weighting colors by
+    /// how much of the pixel the model covers
+    fn downsample(&self, samples: &[Option<Vec3>]) -> Image {
+        let settings = self.settings;
+        let sample_width = settings.width * SUPERSAMPLE;
+        let mut image = Image::new(
+            settings.width,
+            settings.height,
+            settings.background,
+        );
+
+        let [r, g, b, a] = settings.background;
+        let background = Vec3::new(r as f32, g as f32, b as f32) / 255f32;
+        let background_alpha = a as f32 / 255f32;
+
+        for y in 0..settings.height {
+            for x in 0..settings.width {
+                let mut sum = Vec3::ZERO;
+                let mut covered = 0;
+
+                for sy in 0..SUPERSAMPLE {
+                    for sx in 0..SUPERSAMPLE {
+                        let sample_idx = (y * SUPERSAMPLE + sy) * sample_width
+                            + x * SUPERSAMPLE + sx;
+
+                        if let Some(color) = samples[sample_idx] {
+                            sum+= color;
+                            covered+= 1;
+                        }
+                    }
+                }
+
+                if covered == 0 {
+                    continue;
+                }
+
+                let coverage = covered as f32
+                    / (SUPERSAMPLE * SUPERSAMPLE) as f32;
+                let color = sum / covered as f32;
+
+                // Over the background, which may itself be see-through
+                let alpha = coverage + background_alpha * (1f32 - coverage);
+                let color = (color * coverage
+                    + background * background_alpha * (1f32 - coverage))
+                    / alpha;
+
+                let [r, g, b] = (color.clamp(Vec3::ZERO, Vec3::ONE)
+                    * 255f32).round().as_uvec3().to_array();
+                image.pixels[y * settings.width + x] = [
+                    r as u8,
+                    g as u8,
+                    b as u8,
+                    (alpha * 255f32).round() as u8,
+                ];
+            }
+        }
+
+        image
+    }
+}
+
+/// Fills the samples whose centers fall inside the triangle and are
+/// nearer the camera than what was drawn there before
+fn rasterize(
+    triangle: [ScreenVertex; 3],
+    width: usize,
+    height: usize,
+    colors: &mut [Option<Vec3>],
+    depths: &mut [f32],
+) {
+    let [a, b, c] = triangle;
+    let area = edge(a.pos, b.pos, c.pos);
+
+    if area.abs() <= f32::EPSILON {
+        return;
+    }
+
+    let mins = a.pos.min(b.pos).min(c.pos).floor().max(Vec2::ZERO);
+    let maxs = a.pos.max(b.pos).max(c.pos).ceil()
+        .min(Vec2::new(width as f32, height as f32));
+
+    for y in mins.y as usize..maxs.y as usize {
+        for x in mins.x as usize..maxs.x as usize {
+            let p = Vec2::new(x as f32 + 0.5f32, y as f32 + 0.5f32);
+            let weights = Vec3::new(
+                edge(b.pos, c.pos, p),
+                edge(c.pos, a.pos, p),
+                edge(a.pos, b.pos, p),
+            ) / area;
+
+            if weights.min_element() < 0f32 {
+                continue;
+            }
+
+            let idx = y * width + x;
+            let depth = weights.dot(Vec3::new(a.depth, b.depth, c.depth));
+
+            if depth > depths[idx] {
+                depths[idx] = depth;
+                colors[idx] = Some(
+                    a.color * weights.x + b.color * weights.y
+                        + c.color * weights.z
+                );
+            }
+        }
+    }
+}
+
+/// Twice the signed area of the triangle `a`, `b`, `p`
+fn edge(a: Vec2, b: Vec2, p: Vec2) -> f32 {
+    (b - a).perp_dot(p - a)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Two slopes meeting at a ridge that points at the side camera
+    fn roof() -> Mesh {
+        let mut mesh = Mesh::default();
+        let verts = mesh.add_ring(&[
+            Vec3::new(0f32, -1f32, 0f32),
+            Vec3::new(0f32, -1f32, 1f32),
+            Vec3::new(1f32, 0f32, 0f32),
+            Vec3::new(1f32, 0f32, 1f32),
+            Vec3::new(0f32, 1f32, 0f32),
+            Vec3::new(0f32, 1f32, 1f32),
+        ]);
+
+        for face in [[0, 2, 3, 1], [2, 4, 5, 3]] {
+            mesh.add_face(face.map(|c| verts[c]).into(), vec![], None);
+        }
+
+        mesh
+    }
+
+    fn render(mesh: &Mesh, shading: Shading) -> Image {
+        let settings = RenderSettings {
+            shading,
+            width: 32,
+            height: 32,
+            background: [10, 20, 30, 255],
+            ..RenderSettings::default()
+        };
+
+        Renderer {
+            mesh,
+            palette: &Palette::default(),
+            settings: &settings,
+        }.render()
+    }
+
+    #[test]
+    fn shading() {
+        let mesh = roof();
+        let flat = render(&mesh, Shading::Flat);
+        let gouraud = render(&mesh, Shading::Gouraud);
+        // The left slope spans columns 4 to 16 and the right 16 to 28, on
+        // rows 10 to 22
+        let pixel = |image: &Image, x: usize| image.pixels[16 * 32 + x];
+
+        for image in [&flat, &gouraud] {
+            assert_eq!(image.pixels[0], [10, 20, 30, 255]);
+            assert_eq!(image.pixels[32 * 32 - 1], [10, 20, 30, 255]);
+            assert_ne!(pixel(image, 20), [10, 20, 30, 255]);
+        }
+
+        // One shade per slope, or a blend towards the ridge
+        assert_ne!(pixel(&flat, 8), pixel(&flat, 20));
+        assert_eq!(pixel(&flat, 18), pixel(&flat, 26));
+        assert_ne!(pixel(&gouraud, 18), pixel(&gouraud, 26));
+    }
+}
@@ -0,0 +1,154 @@
+use std::collections::HashSet;
+use std::hash::Hash;
+
+use crate::types::cooked::BodySegment;
+use crate::app_error::AppError;
+
+/// Part of the body tree to export
+#[derive(Clone, Debug, Default)]
+pub struct Selection {
+    /// Body index of the segment to export from instead of the root
+    pub root: Option<usize>,
+    /// Place the chosen root at the origin, dropping the transforms of
+    /// the segments leading to it and its own action and disk shift
+    pub reset_root: bool,
+    /// Body indices of subtrees to leave out
+    pub exclude: HashSet<usize>,
+    /// Colors of the segments whose disks are kept, or all if empty
+    pub colors: HashSet<u32>,
+    /// Ids of the disk information entries whose disks are kept, or all
+    /// if empty
+    pub disk_info_ids: HashSet<i32>,
+}
+
+impl Selection {
+    /// Checks that the segments, colors and disk information ids referred
+    /// to are in the tree
+    pub fn check(&self, body: Option<&BodySegment>) -> Result<(), AppError> {
+        let segments: Vec<_> = body.into_iter()
+            .flat_map(|segment| segment.iter())
+            .collect();
+        let indices: HashSet<_> = segments.iter()
+            .map(|segment| segment.index)
+            .collect();
+        let colors: HashSet<_> = segments.iter()
+            .filter_map(|segment| segment.color)
+            .collect();
+        let disk_info_ids: HashSet<_> = segments.iter()
+            .filter_map(|segment| Some(segment.disk_info.as_ref()?.id))
+            .collect();
+
+        for &idx in self.root.iter().chain(&self.exclude) {
+            if !indices.contains(&idx) {
+                return Err(AppError(
+                    format!("Body segment {idx} is not in the tree")
+                ));
+            }
+        }
+
+        if let Some(color) = missing(&self.colors, &colors) {
+            return Err(AppError(
+                format!("No body segment in the tree has color {color}")
+            ));
+        }
+
+        if let Some(id) = missing(&self.disk_info_ids, &disk_info_ids) {
+            return Err(AppError(format!(
+                "No body segment in the tree has disk information id {id}"
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Whether the selection starts at `segment`, when it has not already
+    pub fn starts_at(&self, segment: &BodySegment) -> bool {
+        self.root.is_none_or(|root| root == segment.index)
+    }
+
+    pub fn excludes(&self, segment: &BodySegment) -> bool {
+        self.exclude.contains(&segment.index)
+    }
+
+    /// Whether the segment's disk passes the color and disk information
+    /// filters
+    pub fn keeps_disk(&self, segment: &BodySegment) -> bool {
+        let color_ok = self.colors.is_empty()
+            || segment.color.is_some_and(|c| self.colors.contains(&c));
+        let disk_info_ok = self.disk_info_ids.is_empty()
+            || segment.disk_info.as_ref()
+                .is_some_and(|info| self.disk_info_ids.contains(&info.id));
+
+        color_ok && disk_info_ok
+    }
+}
+
+/// Lowest of the values asked for that isn't among those found
+fn missing<T: Copy + Ord + Hash>(
+    wanted: &HashSet<T>,
+    found: &HashSet<T>,
+) -> Option<T> {
+    wanted.difference(found).min().copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use glam::Vec2;
+
+    use crate::types::cooked::DiskInformation;
+
+    use super::*;
+
+    #[test]
+    fn check_rejects_what_is_missing() {
+        let disk_info = DiskInformation {
+            index: 0,
+            shift: Vec2::ZERO,
+            scale: Vec2::ONE,
+            disk: None,
+            disk_idx: None,
+            id: 7,
+            flags: 0,
+            arr1: [0f32; 4],
+            arr2: [0f32; 4],
+        };
+        let child = BodySegment {
+            index: 1,
+            disk_info: Some(Rc::new(disk_info)),
+            action: 1,
+            value: 1f32,
+            color: None,
+            left: None,
+            right: None,
+        };
+        let body = BodySegment {
+            index: 0,
+            disk_info: None,
+            action: 1,
+            value: 1f32,
+            color: Some(3),
+            left: Some(Box::new(child)),
+            right: None,
+        };
+
+        let check = |selection: Selection| selection.check(Some(&body));
+
+        assert!(check(Selection {
+            root: Some(1),
+            colors: [3].into(),
+            disk_info_ids: [7].into(),
+            ..Selection::default()
+        }).is_ok());
+
+        for selection in [
+            Selection { root: Some(2), ..Selection::default() },
+            Selection { exclude: [5].into(), ..Selection::default() },
+            Selection { colors: [3, 4].into(), ..Selection::default() },
+            Selection { disk_info_ids: [0].into(), ..Selection::default() },
+        ] {
+            assert!(check(selection).is_err());
+        }
+    }
+}
@@ -0,0 +1,273 @@
+use std::rc::Rc;
+
+use glam::{Affine3, Mat3, Vec2, Vec3};
+
+use crate::types::{cooked::BodySegment, Disk, MeshMeta, VertexSource};
+use crate::select::Selection;
+
+/// Receives the geometry of a body tree as it is walked depth first, left
+/// branch before right.  Every `enter_segment` is matched by an
+/// `exit_segment` once the segment's subtree has been walked.
+pub trait MeshSink {
+    /// Entering a segment, in the frame its action leaves the chain in
+    fn enter_segment(&mut self, _segment: &BodySegment, _xform: Affine3) {}
+
+    /// A disk placed by the segment just entered, continuing its chain
+    fn ring(&mut self, ring: &RingEvent);
+
+    /// The disk of the segment just entered was filtered out, ending its
+    /// chain.  Rings after it start new chains.
+    fn gap(&mut self) {}
+
+    /// The chain forks into two branches after the segment just entered
+    fn branch(&mut self, _branch: &Branch) {}
+
+    /// The segment just entered placed the last ring of its chain
+    fn leaf(&mut self) {}
+
+    fn exit_segment(&mut self) {}
+}
+
+/// Disk placed along a chain
+pub struct RingEvent<'a> {
+    /// Segment frame the disk sits in, including the disk shift
+    pub frame: Affine3,
+    /// Disk of the segment, or the last one given further up the chain
+    pub disk: &'a Disk,
+    /// Position of `disk` in the model's disk list
+    pub disk_idx: usize,
+    pub scale: Vec2,
+    pub meta: MeshMeta,
+}
+
+impl RingEvent<'_> {
+    /// Disk vertices, scaled, within the frame, each with whether it is a
+    /// crease
+    pub fn local(&self) -> impl Iterator<Item = (Vec3, bool)> + '_ {
+        scaled_disk(self.disk, self.scale)
+    }
+
+    /// Source of each disk vertex, in the same order
+    pub fn sources(&self) -> impl Iterator<Item = VertexSource> + '_ {
+        self.disk.iter()
+            .enumerate()
+            .map(|(vertex_idx, &(_, attr))| VertexSource {
+                body_idx: self.meta.body_idx,
+                disk_info_idx: self.meta.disk_info_idx,
+                disk_idx: self.disk_idx,
+                vertex_idx,
+                attr,
+            })
+    }
+}
+
+/// Two subtrees continuing a chain from the same frame
+pub struct Branch<'a> {
+    pub segments: [&'a BodySegment; 2],
+    pub xform: Affine3,
+    pub disk: Option<&'a Rc<Disk>>,
+    pub selection: &'a Selection,
+}
+
+impl Branch<'_> {
+    /// Positions of the first ring each subtree will emit, if it starts
+    /// by continuing the chain
+    pub fn first_rings(&self) -> [Option<Vec<Vec3>>; 2] {
+        self.segments.map(|segment| first_ring(
+            Some(segment),
+            self.xform,
+            self.disk,
+            self.selection,
+        ))
+    }
+}
+
+/// Walks the selected part of the body tree, placing a ring for each
+/// segment with a disk.  Segments above the selection still move the frame
+/// and pass their disks down, but send no events.
+pub fn walk_body(
+    body: Option<&BodySegment>,
+    selection: &Selection,
+    sink: &mut impl MeshSink,
+) {
+    let mut walk = Walk { selection, sink, branch_ct: 1 };
+    walk.segment(body, Affine3::IDENTITY, None, false, 0);
+}
+
+struct Walk<'a, S> {
+    selection: &'a Selection,
+    sink: &'a mut S,
+    /// Branch numbers handed out so far
+    branch_ct: usize,
+}
+
+impl<S: MeshSink> Walk<'_, S> {
+    fn new_branch(&mut self) -> usize {
+        self.branch_ct+= 1;
+        self.branch_ct - 1
+    }
+
+    /// Returns whether any ring was emitted continuing the chain, letting
+    /// the caller tell whether its own ring ends it
+    fn segment(
+        &mut self,
+        node: Option<&BodySegment>,
+        mut xform: Affine3,
+        mut disk: Option<(usize, Rc<Disk>)>,
+        mut selected: bool,
+        mut branch: usize,
+    ) -> bool {
+        let Some(segment) = node else {
+            return false;
+        };
+
+        if self.selection.excludes(segment) {
+            return false;
+        }
+
+        if !selected && self.selection.starts_at(segment) {
+            selected = true;
+
+            // Cancel the root's own action and shift too, so its disk
+            // sits at the origin
+            if self.selection.reset_root {
+                xform = segment.local_transform().inverse();
+            }
+        }
+
+        match segment.action_transform() {
+            Some(action_xform) => xform*= action_xform,
+            None => eprintln!("Unrecognized action {}", segment.action),
+        }
+
+        if selected {
+            self.sink.enter_segment(segment, xform);
+        }
+
+        let mut emitted = false;
+        let mut gap = false;
+
+        if let Some(disk_info) = segment.disk_info.as_ref() {
+            xform *= Affine3::from_translation(
+                (disk_info.shift, 0f32).into()
+            );
+
+            disk = disk_info.disk_idx.zip(disk_info.disk.clone()).or(disk);
+
+            if selected && let Some((disk_idx, d)) = disk.as_ref() {
+                if self.selection.keeps_disk(segment) {
+                    self.sink.ring(&RingEvent {
+                        frame: xform,
+                        disk: d,
+                        disk_idx: *disk_idx,
+                        scale: disk_info.scale,
+                        meta: MeshMeta {
+                            body_idx: segment.index,
+                            disk_info_idx: disk_info.index,
+                            color: segment.color,
+                            cap: false,
+                            branch,
+                        },
+                    });
+                    emitted = true;
+                } else {
+                    self.sink.gap();
+                    gap = true;
+                    branch = self.new_branch();
+                }
+            }
+        }
+
+        let children = [&segment.left, &segment.right].map(|child| {
+            child.as_deref()
+                .filter(|child| !self.selection.excludes(child))
+        });
+
+        let mut forks = false;
+
+        if selected && let [Some(left), Some(right)] = children {
+            self.sink.branch(&Branch {
+                segments: [left, right],
+                xform,
+                disk: disk.as_ref().map(|(_, d)| d),
+                selection: self.selection,
+            });
+            forks = true;
+        }
+
+        let left_branch = if forks { self.new_branch() } else { branch };
+        let left_emitted = self.segment(
+            segment.left.as_deref(),
+            xform,
+            disk.clone(),
+            selected,
+            left_branch,
+        );
+        let right_branch = if forks { self.new_branch() } else { branch };
+        let right_emitted = self.segment(
+            segment.right.as_deref(),
+            xform,
+            disk,
+            selected,
+            right_branch,
+        );
+
+        if selected {
+            if emitted && !left_emitted && !right_emitted {
+                self.sink.leaf();
+            }
+
+            self.sink.exit_segment();
+        }
+
+        !gap && (emitted || left_emitted || right_emitted)
+    }
+}
+
+fn scaled_disk(
+    disk: &Disk,
+    scale: Vec2,
+) -> impl Iterator<Item = (Vec3, bool)> + '_ {
+    disk.iter()
+        .map(move |&(v, attr)| {
+            (Mat3::from_diagonal((scale, 1f32).into()) * v, attr != 0)
+        })
+}
+
+/// Positions of the first ring a subtree would emit when walked from
+/// `xform`, looking down the left branch first
+fn first_ring(
+    node: Option<&BodySegment>,
+    mut xform: Affine3,
+    disk: Option<&Rc<Disk>>,
+    selection: &Selection,
+) -> Option<Vec<Vec3>> {
+    let segment = node.filter(|segment| !selection.excludes(segment))?;
+
+    if let Some(action_xform) = segment.action_transform() {
+        xform*= action_xform;
+    }
+
+    if let Some(disk_info) = segment.disk_info.as_ref() {
+        xform *= Affine3::from_translation(
+            (disk_info.shift, 0f32).into()
+        );
+
+        if let Some(d) = disk_info.disk.as_ref().or(disk) {
+            if !selection.keeps_disk(segment) {
+                return None;
+            }
+
+            return Some(
+                scaled_disk(d, disk_info.scale)
+                    .map(|(v, _)| xform.transform_point3(v))
+                    .collect()
+            );
+        }
+    }
+
+    first_ring(segment.left.as_deref(), xform, disk, selection)
+        .or_else(|| {
+            first_ring(segment.right.as_deref(), xform, disk, selection)
+        })
+}
@@ -0,0 +1,164 @@
+use std::collections::HashMap;
+
+use glam::Affine3;
+
+use crate::types::cooked::BodySegment;
+use crate::coords::{Axis, CoordinateSystem};
+use crate::select::Selection;
+
+/// One joint per body segment, in its bind pose
+#[derive(Clone, Debug)]
+pub struct Bone {
+    pub body_idx: usize,
+    /// Axis the action turns about, if it is a rotation.  Turning by the
+    /// action value about a negative axis is turning by minus the value.
+    pub rotation_axis: Option<Axis>,
+    /// Action value, in degrees for rotations
+    pub value: f32,
+    pub parent: Option<usize>,
+    pub local: Affine3,
+    pub world: Affine3,
+    /// Frame the action leaves the chain in, before the disk shift
+    pub joint: Affine3,
+}
+
+/// Body segments flattened so that every bone follows its parent
+#[derive(Clone, Debug, Default)]
+pub struct Skeleton {
+    bones: Vec<Bone>,
+    by_body_idx: HashMap<usize, usize>,
+}
+
+impl Skeleton {
+    /// Bones for the selected part of the body tree.  Color and disk
+    /// filters leave the bones in place, so the hierarchy stays whole.
+    pub fn from_body(
+        body: Option<&BodySegment>,
+        selection: &Selection,
+    ) -> Self {
+        fn from_body_rec(
+            skeleton: &mut Skeleton,
+            selection: &Selection,
+            node: Option<&BodySegment>,
+            parent: Option<usize>,
+            mut parent_world: Affine3,
+        ) {
+            let Some(segment) = node else {
+                return;
+            };
+
+            if selection.excludes(segment) {
+                return;
+            }
+
+            let mut parent = parent;
+
+            if parent.is_none() && selection.reset_root
+                && selection.starts_at(segment)
+            {
+                parent_world = segment.local_transform().inverse();
+            }
+
+            let local = segment.local_transform();
+            let world = parent_world * local;
+            let joint = parent_world
+                * segment.action_transform().unwrap_or(Affine3::IDENTITY);
+
+            if parent.is_some() || selection.starts_at(segment) {
+                let bone_idx = skeleton.bones.len();
+
+                // Without a parent bone, the root is placed in the world
+                skeleton.bones.push(Bone {
+                    body_idx: segment.index,
+                    rotation_axis: match segment.action {
+                        2 => Some(Axis::X),
+                        3 => Some(Axis::Y),
+                        4 => Some(Axis::Z),
+                        _ => None,
+                    },
+                    value: segment.value,
+                    parent,
+                    local: if parent.is_some() { local } else { world },
+                    world,
+                    joint,
+                });
+                skeleton.by_body_idx.insert(segment.index, bone_idx);
+                parent = Some(bone_idx);
+            }
+
+            for child in [&segment.left, &segment.right] {
+                from_body_rec(
+                    skeleton,
+                    selection,
+                    child.as_deref(),
+                    parent,
+                    world,
+                );
+            }
+        }
+
+        let mut skeleton = Self::default();
+        from_body_rec(&mut skeleton, selection, body, None, Affine3::IDENTITY);
+        skeleton
+    }
+
+    /// Copy of the skeleton with its transforms in other coordinates
+    pub fn transformed(&self, coords: &CoordinateSystem) -> Self {
+        let mut skeleton = self.clone();
+
+        for bone in &mut skeleton.bones {
+            // Mirrored axes turn the other way
+            bone.rotation_axis = bone.rotation_axis.map(|axis| {
+                let axis = coords.axis(axis);
+                Axis { negative: axis.negative != coords.mirrors(), ..axis }
+            });
+            bone.local = coords.transform(bone.local);
+            bone.world = coords.transform(bone.world);
+            bone.joint = coords.transform(bone.joint);
+        }
+
+        skeleton
+    }
+
+    pub fn bones(&self) -> &[Bone] {
+        &self.bones[..]
+    }
+
+    /// Positions of the bones whose parent is `parent`
+    pub fn children(&self, parent: usize) -> impl Iterator<Item = usize> {
+        self.bones.iter()
+            .enumerate()
+            .filter(move |(_, bone)| bone.parent == Some(parent))
+            .map(|(idx, _)| idx)
+    }
+
+    /// Position of the bone for a body segment
+    pub fn bone_idx(&self, body_idx: usize) -> Option<usize> {
+        self.by_body_idx.get(&body_idx).copied()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec3;
+
+    use crate::fixtures::chain_body;
+
+    use super::*;
+
+    #[test]
+    fn reset_root_starts_at_origin() {
+        let body = chain_body(3);
+        let selection = Selection {
+            root: Some(1),
+            reset_root: true,
+            ..Selection::default()
+        };
+        let skeleton = Skeleton::from_body(Some(&body), &selection);
+
+        let origins: Vec<_> = skeleton.bones().iter()
+            .map(|bone| bone.world.translation)
+            .collect();
+        assert_eq!(origins, [Vec3::ZERO, Vec3::Z]);
+    }
+}
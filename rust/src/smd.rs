@@ -0,0 +1,181 @@
+use std::fmt;
+use std::io::{self, Write};
+
+use glam::{Affine3, EulerRot, Vec3};
+
+use fmt::{Formatter, Display};
+use crate::types::{fan, Mesh};
+use crate::skeleton::Skeleton;
+use crate::palette::material_name;
+use crate::export::Exporter;
+
+/// Valve studiomdl source file.  With a mesh, this is a reference SMD whose
+/// triangles are each bound to the bone of the segment that produced them;
+/// without one, it is a skeletal animation holding only the bind pose.
+pub struct Smd<'a> {
+    pub skeleton: &'a Skeleton,
+    pub mesh: Option<&'a Mesh>,
+}
+
+impl Smd<'_> {
+    /// Mesh vertex behind each triangle corner, in the order written
+    pub fn vertex_order(&self) -> Vec<usize> {
+        self.mesh.into_iter()
+            .flat_map(|mesh| mesh.faces())
+            .flat_map(|(face, _)| fan(face).flatten())
+            .collect()
+    }
+}
+
+impl Display for Smd<'_> {
+    fn fmt(&self, formatter: &mut Formatter) -> Result<(), fmt::Error> {
+        let bones = self.skeleton.bones();
+
+        writeln!(formatter, "version 1")?;
+        writeln!(formatter, "nodes")?;
+
+        for (bone_idx, bone) in bones.iter().enumerate() {
+            let parent = bone.parent.map(|p| p as i64).unwrap_or(-1);
+            writeln!(
+                formatter,
+                "{bone_idx} \"segment_{}\" {parent}",
+                bone.body_idx,
+            )?;
+        }
+
+        writeln!(formatter, "end")?;
+        writeln!(formatter, "skeleton")?;
+        writeln!(formatter, "time 0")?;
+
+        for (bone_idx, bone) in bones.iter().enumerate() {
+            let (position, rotation) = position_rotation(bone.local);
+            let [x, y, z] = position.into();
+            let [rx, ry, rz] = rotation.into();
+
+            writeln!(
+                formatter,
+                "{bone_idx} {x:.6} {y:.6} {z:.6} {rx:.6} {ry:.6} {rz:.6}",
+            )?;
+        }
+
+        writeln!(formatter, "end")?;
+
+        if let Some(mesh) = self.mesh {
+            let verts = mesh.verts();
+            let uvs = mesh.uvs();
+            let normals = mesh.corner_normals();
+
+            writeln!(formatter, "triangles")?;
+
+            for (face_idx, (face, meta)) in mesh.faces().enumerate() {
+                let color = meta.and_then(|m| m.color);
+                let bone_idx = meta
+                    .and_then(|m| self.skeleton.bone_idx(m.body_idx))
+                    .unwrap_or(0);
+
+                let face_uvs = mesh.face_uvs(face_idx);
+                let corners: Vec<_> = (0..face.len()).collect();
+
+                for tri in fan(&corners[..]) {
+                    writeln!(formatter, "{}.bmp", material_name(color))?;
+
+                    for corner in tri {
+                        let normal_idx = normals.faces[face_idx][corner];
+                        let [x, y, z] = verts[face[corner]].into();
+                        let [nx, ny, nz] = normals.normals[normal_idx].into();
+                        let [u, v] = face_uvs.get(corner)
+                            .map(|&uv_idx| uvs[uv_idx])
+                            .unwrap_or_default()
+                            .into();
+
+                        writeln!(
+                            formatter,
+                            "{bone_idx} {x:.6} {y:.6} {z:.6} \
+                                {nx:.6} {ny:.6} {nz:.6} {u:.6} {v:.6}",
+                        )?;
+                    }
+                }
+            }
+
+            writeln!(formatter, "end")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Exporter for Smd<'_> {
+    fn export(&self, writer: &mut dyn Write) -> io::Result<()> {
+        write!(writer, "{self}")
+    }
+}
+
+/// Splits a bone transform into a position and XYZ Euler angles in radians
+fn position_rotation(xform: Affine3) -> (Vec3, Vec3) {
+    let (z, y, x) = xform.matrix3.to_euler(EulerRot::ZYX);
+    (xform.translation, Vec3::new(x, y, z))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fixtures::{two_ring_mesh, two_segment_body};
+    use crate::select::Selection;
+
+    use super::*;
+
+    #[test]
+    fn snapshot() {
+        let mesh = two_ring_mesh();
+        let body = two_segment_body();
+        let skeleton = Skeleton::from_body(Some(&body), &Selection::default());
+
+        let reference = Smd { skeleton: &skeleton, mesh: Some(&mesh) };
+        let animation = Smd { skeleton: &skeleton, mesh: None };
+
+        assert_eq!(
+            reference.to_string(),
+            include_str!("smd/two_rings.smd"),
+        );
+        assert_eq!(
+            animation.to_string(),
+            include_str!("smd/two_rings_anim.smd"),
+        );
+    }
+
+    #[test]
+    fn vertex_order_follows_triangles() {
+        let mut mesh = Mesh::default();
+        let verts = mesh.add_ring(&[
+            Vec3::ZERO, Vec3::X, Vec3::ONE, Vec3::Y, Vec3::Z,
+        ]);
+        mesh.add_face(verts[..4].to_vec(), Vec::new(), None);
+        mesh.add_face(vec![verts[4], verts[1], verts[0]], Vec::new(), None);
+
+        let skeleton = Skeleton::from_body(None, &Selection::default());
+        let smd = Smd { skeleton: &skeleton, mesh: Some(&mesh) };
+        let order = smd.vertex_order();
+
+        assert_eq!(order, [0, 1, 2, 0, 2, 3, 4, 1, 0]);
+
+        // Each corner written sits on the vertex the order gives for it
+        let text = smd.to_string();
+        let corners: Vec<_> = text.lines()
+            .skip_while(|&line| line != "triangles")
+            .filter(|line| line.split(' ').count() == 9)
+            .map(|line| {
+                let fields: Vec<f32> = line.split(' ')
+                    .map(|field| field.parse().unwrap())
+                    .collect();
+                Vec3::from_slice(&fields[1..4])
+            })
+            .collect();
+
+        let expected: Vec<_> = order.iter()
+            .map(|&idx| mesh.verts()[idx])
+            .collect();
+        assert_eq!(corners, expected);
+
+        let animation = Smd { skeleton: &skeleton, mesh: None };
+        assert!(animation.vertex_order().is_empty());
+    }
+}
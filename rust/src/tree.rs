@@ -0,0 +1,322 @@
+use std::fmt;
+use std::io::{self, Write};
+use std::collections::HashSet;
+
+use fmt::{Formatter, Display};
+use crate::types::{action_name, cooked, raw, Model};
+use crate::export::Exporter;
+
+/// Indented listing of the body tree
+pub struct TextTree<'a> {
+    pub model: &'a Model,
+}
+
+/// Graphviz graph of the body tree.  Left edges are solid, right edges
+/// dashed, and segments unreachable from the root are drawn greyed out in
+/// a separate cluster.
+pub struct DotTree<'a> {
+    pub model: &'a Model,
+}
+
+struct SegmentLabel {
+    index: usize,
+    action: i32,
+    value: f32,
+    color: Option<u32>,
+    disk_info_idx: Option<usize>,
+}
+
+impl SegmentLabel {
+    fn from_cooked(segment: &cooked::BodySegment) -> Self {
+        Self {
+            index: segment.index,
+            action: segment.action,
+            value: segment.value,
+            color: segment.color,
+            disk_info_idx: segment.disk_info.as_ref().map(|info| info.index),
+        }
+    }
+
+    fn from_raw(segment: &raw::BodySegment, index: usize) -> Self {
+        Self {
+            index,
+            action: segment.action,
+            value: segment.value,
+            color: u32::try_from(segment.color).ok(),
+            disk_info_idx: usize::try_from(segment.disk_info_idx).ok(),
+        }
+    }
+
+    fn action(&self) -> String {
+        match action_name(self.action) {
+            Some(name) => format!("{name} {}", self.value),
+            None => format!("action {} {}", self.action, self.value),
+        }
+    }
+
+    fn color(&self) -> String {
+        self.color.map(|c| c.to_string()).unwrap_or("-".into())
+    }
+
+    fn disk_info(&self) -> String {
+        self.disk_info_idx.map(|i| i.to_string()).unwrap_or("-".into())
+    }
+}
+
+impl Display for SegmentLabel {
+    fn fmt(&self, formatter: &mut Formatter) -> Result<(), fmt::Error> {
+        write!(
+            formatter,
+            "[{}] {} color={} disk_info={}",
+            self.index,
+            self.action(),
+            self.color(),
+            self.disk_info(),
+        )
+    }
+}
+
+fn unreachable(model: &Model) -> Vec<usize> {
+    let reachable: HashSet<_> = model.body().into_iter()
+        .flat_map(|root| root.iter())
+        .map(|segment| segment.index)
+        .collect();
+
+    (0..model.raw_body().len())
+        .filter(|idx| !reachable.contains(idx))
+        .collect()
+}
+
+impl Display for TextTree<'_> {
+    fn fmt(&self, formatter: &mut Formatter) -> Result<(), fmt::Error> {
+        fn fmt_rec(
+            formatter: &mut Formatter,
+            node: Option<&cooked::BodySegment>,
+            side: &str,
+            depth: usize,
+        ) -> Result<(), fmt::Error> {
+            if let Some(segment) = node {
+                writeln!(
+                    formatter,
+                    "{}{side}{}",
+                    "  ".repeat(depth),
+                    SegmentLabel::from_cooked(segment),
+                )?;
+
+                fmt_rec(formatter, segment.left.as_deref(), "L ", depth + 1)?;
+                fmt_rec(formatter, segment.right.as_deref(), "R ", depth + 1)?;
+            }
+
+            Ok(())
+        }
+
+        fmt_rec(formatter, self.model.body(), "", 0)?;
+
+        let unreachable = unreachable(self.model);
+
+        if !unreachable.is_empty() {
+            writeln!(formatter, "unreachable:")?;
+
+            for idx in unreachable {
+                let label = SegmentLabel::from_raw(
+                    &self.model.raw_body()[idx],
+                    idx,
+                );
+
+                writeln!(formatter, "  {label}")?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Exporter for TextTree<'_> {
+    fn export(&self, writer: &mut dyn Write) -> io::Result<()> {
+        write!(writer, "{self}")
+    }
+}
+
+impl Display for DotTree<'_> {
+    fn fmt(&self, formatter: &mut Formatter) -> Result<(), fmt::Error> {
+        fn fmt_node(
+            formatter: &mut Formatter,
+            indent: &str,
+            label: &SegmentLabel,
+        ) -> Result<(), fmt::Error> {
+            writeln!(
+                formatter,
+                "{indent}s{} [label=\"#{}\\n{}\\ncolor {}\\ndisk info {}\"];",
+                label.index,
+                label.index,
+                label.action(),
+                label.color(),
+                label.disk_info(),
+            )
+        }
+
+        fn fmt_edge(
+            formatter: &mut Formatter,
+            indent: &str,
+            from: usize,
+            to: usize,
+            left: bool,
+        ) -> Result<(), fmt::Error> {
+            let (side, style) = if left {
+                ("L", "solid")
+            } else {
+                ("R", "dashed")
+            };
+
+            writeln!(
+                formatter,
+                "{indent}s{from} -> s{to} [label=\"{side}\", style={style}];",
+            )
+        }
+
+        writeln!(formatter, "digraph body {{")?;
+        writeln!(formatter, "    node [shape=box, fontname=\"monospace\"];")?;
+
+        let segments = self.model.body().into_iter()
+            .flat_map(|root| root.iter());
+
+        for segment in segments {
+            let children = [(&segment.left, true), (&segment.right, false)];
+            fmt_node(formatter, "    ", &SegmentLabel::from_cooked(segment))?;
+
+            for (child, left) in children {
+                if let Some(child) = child {
+                    fmt_edge(
+                        formatter,
+                        "    ",
+                        segment.index,
+                        child.index,
+                        left,
+                    )?;
+                }
+            }
+        }
+
+        let unreachable = unreachable(self.model);
+
+        if !unreachable.is_empty() {
+            let raw_body = self.model.raw_body();
+
+            writeln!(formatter, "    subgraph cluster_unreachable {{")?;
+            writeln!(formatter, "        label=\"unreachable\";")?;
+            writeln!(formatter, "        style=dashed;")?;
+            writeln!(formatter, "        node [color=grey, fontcolor=grey];")?;
+
+            for &idx in &unreachable {
+                let label = SegmentLabel::from_raw(&raw_body[idx], idx);
+                fmt_node(formatter, "        ", &label)?;
+            }
+
+            for &idx in &unreachable {
+                let segment = &raw_body[idx];
+                let children = [(segment.left, true), (segment.right, false)];
+
+                for (child, left) in children {
+                    if let Ok(child) = usize::try_from(child)
+                        && unreachable.contains(&child)
+                    {
+                        fmt_edge(formatter, "        ", idx, child, left)?;
+                    }
+                }
+            }
+
+            writeln!(formatter, "    }}")?;
+        }
+
+        writeln!(formatter, "}}")?;
+
+        Ok(())
+    }
+}
+
+impl Exporter for DotTree<'_> {
+    fn export(&self, writer: &mut dyn Write) -> io::Result<()> {
+        write!(writer, "{self}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{Vec2, Vec3};
+
+    use crate::types::ModelConfig;
+
+    use super::*;
+
+    /// Root forking into a turn and a shift, with segments 3 and 4 left
+    /// over
+    fn model() -> Model {
+        let segment = |disk_info_idx, action, value, color, left, right| {
+            raw::BodySegment {
+                disk_info_idx,
+                action,
+                value,
+                color,
+                left,
+                right,
+            }
+        };
+
+        Model::new(ModelConfig {
+            disks: vec![vec![(Vec3::X, 0), (Vec3::Y, 0), (-Vec3::X, 0)]],
+            disk_info: vec![raw::DiskInformation {
+                shift: Vec2::ZERO,
+                scale: Vec2::ONE,
+                disk_idx: 0,
+                id: 7,
+                flags: 0,
+                arr1: [0f32; 4],
+                arr2: [0f32; 4],
+            }],
+            body: vec![
+                segment(0, 1, 2f32, 1, 1, 2),
+                segment(-1, 4, 90f32, -1, -1, -1),
+                segment(-1, 0, 1.5f32, 3, -1, -1),
+                segment(0, 1, 0.5f32, 2, -1, 4),
+                segment(-1, 2, 10f32, -1, -1, -1),
+            ],
+        })
+    }
+
+    #[test]
+    fn text() {
+        let model = model();
+
+        assert_eq!(TextTree { model: &model }.to_string(), "\
+[0] shift 2 color=1 disk_info=0
+  L [1] rotate_z 90 color=- disk_info=-
+  R [2] shift 1.5 color=3 disk_info=-
+unreachable:
+  [3] shift 0.5 color=2 disk_info=0
+  [4] rotate_x 10 color=- disk_info=-
+");
+    }
+
+    #[test]
+    fn dot() {
+        let model = model();
+
+        assert_eq!(DotTree { model: &model }.to_string(), r##"digraph body {
+    node [shape=box, fontname="monospace"];
+    s0 [label="#0\nshift 2\ncolor 1\ndisk info 0"];
+    s0 -> s1 [label="L", style=solid];
+    s0 -> s2 [label="R", style=dashed];
+    s1 [label="#1\nrotate_z 90\ncolor -\ndisk info -"];
+    s2 [label="#2\nshift 1.5\ncolor 3\ndisk info -"];
+    subgraph cluster_unreachable {
+        label="unreachable";
+        style=dashed;
+        node [color=grey, fontcolor=grey];
+        s3 [label="#3\nshift 0.5\ncolor 2\ndisk info 0"];
+        s4 [label="#4\nrotate_x 10\ncolor -\ndisk info -"];
+        s3 -> s4 [label="R", style=dashed];
+    }
+}
+"##);
+    }
+}
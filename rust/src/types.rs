@@ -1,10 +1,12 @@
 use std::fmt;
 use std::rc::Rc;
-use std::collections::{HashSet, HashMap};
+use std::cmp::Ordering;
+use std::collections::{HashSet, HashMap, BTreeSet};
 
-use glam::Vec3;
+use glam::{IVec3, Vec2, Vec3};
 
 use fmt::{Formatter, Debug, Display};
+use crate::coords::CoordinateSystem;
 
 
 #[derive(Clone)]
@@ -36,6 +38,16 @@ pub type DiskVertex = (Vec3, i32);
 
 pub type Disk = Vec<DiskVertex>;
 
+pub fn action_name(action: i32) -> Option<&'static str> {
+    match action {
+        0 | 1 => Some("shift"),
+        2 => Some("rotate_x"),
+        3 => Some("rotate_y"),
+        4 => Some("rotate_z"),
+        _ => None,
+    }
+}
+
 pub mod raw {
     use glam::Vec2;
 
@@ -65,7 +77,7 @@ pub mod raw {
 
 pub mod cooked {
     use std::rc::Rc;
-    use glam::Vec2;
+    use glam::{Affine3, Vec2};
 
     #[derive(Clone, Debug)]
     #[allow(dead_code)]
@@ -79,6 +91,47 @@ pub mod cooked {
         pub right: Option<Box<BodySegment>>,
     }
 
+    impl BodySegment {
+        /// Transform applied by the segment's action, if recognized
+        pub fn action_transform(&self) -> Option<Affine3> {
+            match self.action {
+                0 | 1 => Some(Affine3::from_translation(
+                    [0f32, 0f32, self.value].into()
+                )),
+                2 => Some(Affine3::from_rotation_x(self.value.to_radians())),
+                3 => Some(Affine3::from_rotation_y(self.value.to_radians())),
+                4 => Some(Affine3::from_rotation_z(self.value.to_radians())),
+                _ => None,
+            }
+        }
+
+        /// Transform from the parent segment's frame to this segment's,
+        /// including the disk shift
+        pub fn local_transform(&self) -> Affine3 {
+            let xform = self.action_transform().unwrap_or(Affine3::IDENTITY);
+
+            if let Some(disk_info) = self.disk_info.as_ref() {
+                xform * Affine3::from_translation(
+                    (disk_info.shift, 0f32).into()
+                )
+            } else {
+                xform
+            }
+        }
+
+        /// Segments reachable from this one, depth first, left before right
+        pub fn iter(&self) -> impl Iterator<Item = &BodySegment> {
+            let mut stack = vec![self];
+
+            std::iter::from_fn(move || {
+                let segment = stack.pop()?;
+                stack.extend(segment.right.as_deref());
+                stack.extend(segment.left.as_deref());
+                Some(segment)
+            })
+        }
+    }
+
     #[derive(Clone, Debug)]
     #[allow(dead_code)]
     pub struct DiskInformation {
@@ -86,6 +139,8 @@ pub mod cooked {
         pub shift: Vec2,
         pub scale: Vec2,
         pub disk: Option<Rc<crate::types::Disk>>,
+        /// Position of `disk` in the model's disk list
+        pub disk_idx: Option<usize>,
         pub id: i32,
         pub flags: i32,
         pub arr1: [f32; 4],
@@ -98,14 +153,15 @@ pub mod cooked {
             index: usize,
             disks: &[Rc<crate::types::Disk>],
         ) -> Self {
-            let disk = usize::try_from(raw_info.disk_idx).ok()
-                .and_then(|idx| disks.get(idx).map(Rc::clone));
+            let disk_idx = usize::try_from(raw_info.disk_idx).ok()
+                .filter(|&idx| idx < disks.len());
 
             Self {
                 index,
                 shift: raw_info.shift,
                 scale: raw_info.scale,
-                disk,
+                disk: disk_idx.map(|idx| Rc::clone(&disks[idx])),
+                disk_idx,
                 id: raw_info.id,
                 flags: raw_info.flags,
                 arr1: raw_info.arr1,
@@ -128,6 +184,7 @@ pub struct ModelConfig {
 pub struct Model {
     disks: Vec<Rc<Disk>>,
     disk_info: Vec<Rc<cooked::DiskInformation>>,
+    raw_body: Vec<raw::BodySegment>,
     body: Option<Box<cooked::BodySegment>>,
 }
 
@@ -153,11 +210,12 @@ impl Model {
                 ))
             ).collect();
 
-        let body = body_from_raw(raw_body, &disk_info[..]);
+        let body = body_from_raw(&raw_body[..], &disk_info[..]);
 
         Self {
             disks,
             disk_info,
+            raw_body,
             body
         }
     }
@@ -168,6 +226,11 @@ impl Model {
         self.body.as_deref()
     }
 
+    /// Body segments as stored, including any unreachable from the root
+    pub fn raw_body(&self) -> &[raw::BodySegment] {
+        &self.raw_body[..]
+    }
+
     #[allow(dead_code)]
     pub fn disk_size(&self) -> usize {
         self.disks.first().map(|v| v.len()).unwrap_or(0)
@@ -175,7 +238,7 @@ impl Model {
 }
 
 pub fn body_from_raw(
-    raw_body: Vec<raw::BodySegment>,
+    raw_body: &[raw::BodySegment],
     disk_info: &[Rc<cooked::DiskInformation>],
 ) -> Option<Box<cooked::BodySegment>> {
     let mut visited = HashSet::<usize>::new();
@@ -229,83 +292,916 @@ pub fn body_from_raw(
         }))
     }
 
-    body_from_raw_rec(&raw_body[..], disk_info, 0, &mut visited)
+    body_from_raw_rec(raw_body, disk_info, 0, &mut visited)
 }
 
 #[derive(Clone, Debug, Default)]
 pub struct Mesh {
     verts: Vec<Vec3>,
-    indices: Vec<usize>,
+    faces: Vec<Vec<usize>>,
     meta: HashMap<usize, MeshMeta>,
+    /// Vertices whose disk attribute marks a corner in the profile
+    creases: HashSet<usize>,
+    /// Disk vertices that vertices were placed from
+    sources: HashMap<usize, VertexSource>,
+    uvs: Vec<Vec2>,
+    /// Texture coordinates per face corner, empty for faces without any
+    face_uvs: Vec<Vec<usize>>,
+}
+
+/// Where a mesh vertex came from in the model
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct VertexSource {
+    pub body_idx: usize,
+    pub disk_info_idx: usize,
+    pub disk_idx: usize,
+    /// Position of the vertex within its disk
+    pub vertex_idx: usize,
+    pub attr: i32,
+}
+
+/// Normals shared between corners, indexed per face corner
+#[derive(Clone, Debug, Default)]
+pub struct CornerNormals {
+    pub normals: Vec<Vec3>,
+    /// One list per face, holding an index into `normals` for each corner
+    pub faces: Vec<Vec<usize>>,
 }
 
 impl Mesh {
-    #[allow(dead_code)]
-    pub fn add_disk(&mut self, disk: &[Vec3], meta: Option<MeshMeta>) {
-        let disk_size = disk.len();
-        let new = self.verts.is_empty();
-        let start_idx = self.verts.len() - disk.len();
-
-        self.verts.extend(disk);
-        
-        if !new {
-            if let Some(m) = meta {
-                self.meta.insert(self.indices.len() / 4, m);
-            }
+    /// Appends a ring of vertices, returning their indices
+    pub fn add_ring(&mut self, ring: &[Vec3]) -> Vec<usize> {
+        let start_idx = self.verts.len();
+        self.verts.extend(ring);
+        (start_idx..self.verts.len()).collect()
+    }
 
-            for idx in 0..disk_size {
-                let idx1 = idx + start_idx;
-                let idx2 = (idx + 1) % disk_size + start_idx;
-                let idx3 = idx2 + disk_size;
-                let idx4 = idx1 + disk_size;
-                self.indices.extend(&[idx1, idx2, idx3, idx4]);
-            }
+    pub fn mark_crease(&mut self, idx: usize) {
+        self.creases.insert(idx);
+    }
+
+    pub fn is_crease(&self, idx: usize) -> bool {
+        self.creases.contains(&idx)
+    }
+
+    pub fn set_source(&mut self, idx: usize, source: VertexSource) {
+        self.sources.insert(idx, source);
+    }
+
+    pub fn source(&self, idx: usize) -> Option<&VertexSource> {
+        self.sources.get(&idx)
+    }
+
+    pub fn add_vert(&mut self, vert: Vec3) -> usize {
+        self.verts.push(vert);
+        self.verts.len() - 1
+    }
+
+    /// Appends texture coordinates, returning their indices
+    pub fn add_uvs(&mut self, uvs: &[Vec2]) -> Vec<usize> {
+        let start_idx = self.uvs.len();
+        self.uvs.extend(uvs);
+        (start_idx..self.uvs.len()).collect()
+    }
+
+    /// Appends a polygon, starting a new group if `meta` is given.  `uvs`
+    /// holds a texture coordinate for each corner, or none at all.
+    pub fn add_face(
+        &mut self,
+        face: Vec<usize>,
+        uvs: Vec<usize>,
+        meta: Option<MeshMeta>,
+    ) {
+        if let Some(m) = meta {
+            self.meta.insert(self.faces.len(), m);
         }
+
+        self.faces.push(face);
+        self.face_uvs.push(uvs);
     }
 
-    pub fn add_loop(
+    /// Connects two rings with a band of quads.  Each ring's texture
+    /// coordinates repeat its first vertex at the end, so the last quad can
+    /// close the seam.  Rings of different sizes are zipped together,
+    /// pairing vertices by how far around their ring they are, with
+    /// triangles wherever one ring gets ahead of the other.
+    pub fn add_band(
         &mut self,
-        start_disk: &[Vec3],
-        end_disk: &[Vec3],
-        meta: Option<MeshMeta>
+        (start_ring, start_uvs): (&[usize], &[usize]),
+        (end_ring, end_uvs): (&[usize], &[usize]),
+        mut meta: Option<MeshMeta>,
     ) {
-        self.verts.extend(start_disk);
-        self.add_disk(end_disk, meta);
+        let (start_len, end_len) = (start_ring.len(), end_ring.len());
+
+        if start_len == 0 || end_len == 0 {
+            return;
+        }
+
+        let (mut start_idx, mut end_idx) = (0, 0);
+
+        while start_idx < start_len || end_idx < end_len {
+            let idx1 = start_ring[start_idx % start_len];
+            let idx2 = start_ring[(start_idx + 1) % start_len];
+            let idx3 = end_ring[(end_idx + 1) % end_len];
+            let idx4 = end_ring[end_idx % end_len];
+            let uv1 = start_uvs[start_idx];
+            let uv2 = start_uvs[(start_idx + 1).min(start_len)];
+            let uv3 = end_uvs[(end_idx + 1).min(end_len)];
+
+            let uv4 = end_uvs[end_idx];
+
+            let start_ahead = (start_idx + 1) * end_len;
+            let end_ahead = (end_idx + 1) * start_len;
+
+            let (face, uvs) = match start_ahead.cmp(&end_ahead) {
+                Ordering::Equal => {
+                    start_idx+= 1;
+                    end_idx+= 1;
+                    (vec![idx1, idx2, idx3, idx4], vec![uv1, uv2, uv3, uv4])
+                },
+                Ordering::Less => {
+                    start_idx+= 1;
+                    (vec![idx1, idx2, idx4], vec![uv1, uv2, uv4])
+                },
+                Ordering::Greater => {
+                    end_idx+= 1;
+                    (vec![idx1, idx3, idx4], vec![uv1, uv3, uv4])
+                },
+            };
+
+            self.add_face(face, uvs, meta.take());
+        }
     }
-}
 
-impl Display for Mesh {
-    fn fmt(&self, formatter: &mut Formatter) -> Result<(), fmt::Error> {
-        for v in &self.verts[..] {
-            let [x, y, z] = (*v).into();
-            // Swizzle y and z
-            writeln!(formatter, "v {x:.9} {z:.9} {y:.9}")?;
-        }
-
-        // Flip faces
-        for (idx_idx, &[mut idx1, mut idx4, mut idx3, mut idx2])
-            in (self.indices.as_chunks().0).iter().enumerate()
-        {
-            idx1+= 1;
-            idx2+= 1;
-            idx3+= 1;
-            idx4+= 1;
-
-            if let Some(meta) = self.meta.get(&idx_idx) {
-                writeln!(formatter, "{meta}")?;
+    /// Merges vertices lying within `tolerance` of each other, dropping
+    /// faces that collapse to fewer than three distinct vertices
+    pub fn weld(&mut self, tolerance: f32) {
+        let cell_size = tolerance.max(f32::EPSILON);
+        let cell = |v: Vec3| (v / cell_size).floor().as_ivec3();
+
+        let mut grid = HashMap::<IVec3, Vec<usize>>::new();
+        let mut remap = Vec::with_capacity(self.verts.len());
+        let mut verts = Vec::<Vec3>::new();
+
+        for &v in &self.verts {
+            let home = cell(v);
+            let mut found = None;
+
+            'search: for dx in -1..=1 {
+                for dy in -1..=1 {
+                    for dz in -1..=1 {
+                        let key = home + IVec3::new(dx, dy, dz);
+
+                        for &idx in grid.get(&key).into_iter().flatten() {
+                            if verts[idx].distance(v) <= tolerance {
+                                found = Some(idx);
+                                break 'search;
+                            }
+                        }
+                    }
+                }
             }
 
-            writeln!(formatter, "f {idx1} {idx2} {idx3} {idx4}")?;
+            let idx = found.unwrap_or_else(|| {
+                verts.push(v);
+                grid.entry(home).or_default().push(verts.len() - 1);
+                verts.len() - 1
+            });
+
+            remap.push(idx);
         }
 
-        Ok(())
+        let old_faces = std::mem::take(&mut self.faces);
+        let old_uvs = std::mem::take(&mut self.face_uvs);
+        let mut old_meta = std::mem::take(&mut self.meta);
+        let mut pending_meta = None;
+
+        self.verts = verts;
+        self.creases = self.creases.iter()
+            .map(|&idx| remap[idx])
+            .collect();
+
+        // Merged vertices keep the source of the first one placed
+        let old_sources = std::mem::take(&mut self.sources);
+
+        for (old_idx, &idx) in remap.iter().enumerate() {
+            if let Some(&source) = old_sources.get(&old_idx) {
+                self.sources.entry(idx).or_insert(source);
+            }
+        }
+
+        let faces = old_faces.into_iter().zip(old_uvs);
+
+        for (face_idx, (face, uvs)) in faces.enumerate() {
+            if let Some(m) = old_meta.remove(&face_idx) {
+                pending_meta = Some(m);
+            }
+
+            // Corners as (vertex, texture coordinate) pairs, so collapsed
+            // corners drop their coordinates along with them
+            let mut corners: Vec<_> = face.into_iter()
+                .enumerate()
+                .map(|(corner, idx)| (remap[idx], uvs.get(corner).copied()))
+                .collect();
+
+            corners.dedup_by_key(|&mut (idx, _)| idx);
+
+            while corners.len() > 1
+                && corners.first().map(|c| c.0) == corners.last().map(|c| c.0)
+            {
+                corners.pop();
+            }
+
+            let distinct = (0..corners.len())
+                .filter(|&i| !corners[..i].iter().any(|c| c.0 == corners[i].0))
+                .count();
+
+            if distinct >= 3 {
+                let (face, uvs): (Vec<_>, Vec<_>) = corners.into_iter()
+                    .unzip();
+                let uvs = uvs.into_iter().collect::<Option<_>>()
+                    .unwrap_or_default();
+
+                self.add_face(face, uvs, pending_meta.take());
+            }
+        }
+    }
+
+    /// Inserts vertices lying along face edges, within `tolerance`, into
+    /// those faces, so that faces meeting at an edge split it at the same
+    /// places.  Inserted corners get texture coordinates part way along the
+    /// edge.
+    pub fn split_edges(&mut self, tolerance: f32) {
+        // Vertices in use, by X, to find those near each edge quickly
+        let mut used: Vec<_> = self.faces.iter().flatten().copied().collect();
+        used.sort_by(|&a, &b| {
+            self.verts[a].x.total_cmp(&self.verts[b].x).then(a.cmp(&b))
+        });
+        used.dedup();
+
+        for face_idx in 0..self.faces.len() {
+            let face = &self.faces[face_idx];
+            let len = face.len();
+            let splits: Vec<_> = (0..len)
+                .map(|corner| {
+                    self.edge_splits(face, corner, &used[..], tolerance)
+                })
+                .collect();
+
+            if splits.iter().all(Vec::is_empty) {
+                continue;
+            }
+
+            let face = std::mem::take(&mut self.faces[face_idx]);
+            let uvs = std::mem::take(&mut self.face_uvs[face_idx]);
+            let mut new_face = Vec::new();
+            let mut new_uvs = Vec::new();
+
+            for (corner, between) in splits.into_iter().enumerate() {
+                new_face.push(face[corner]);
+                new_face.extend(between.iter().map(|&(_, idx)| idx));
+
+                if let Some(&uv) = uvs.get(corner) {
+                    let start = self.uvs[uv];
+                    let end = self.uvs[uvs[(corner + 1) % len]];
+                    new_uvs.push(uv);
+
+                    for &(t, _) in &between {
+                        self.uvs.push(start.lerp(end, t));
+                        new_uvs.push(self.uvs.len() - 1);
+                    }
+                }
+            }
+
+            self.faces[face_idx] = new_face;
+            self.face_uvs[face_idx] = new_uvs;
+        }
+    }
+
+    /// Vertices among `used` lying inside the edge leaving a face's
+    /// corner, with how far along it they are, in order
+    fn edge_splits(
+        &self,
+        face: &[usize],
+        corner: usize,
+        used: &[usize],
+        tolerance: f32,
+    ) -> Vec<(f32, usize)> {
+        let start = self.verts[face[corner]];
+        let end = self.verts[face[(corner + 1) % face.len()]];
+        let edge = end - start;
+        let first = used.partition_point(|&idx| {
+            self.verts[idx].x < start.x.min(end.x) - tolerance
+        });
+
+        let mut splits: Vec<_> = used[first..].iter()
+            .take_while(|&&idx| {
+                self.verts[idx].x <= start.x.max(end.x) + tolerance
+            })
+            .filter(|idx| !face.contains(idx))
+            .filter_map(|&idx| {
+                let v = self.verts[idx];
+                let t = (v - start).dot(edge) / edge.length_squared();
+                let inside = v.distance(start) > tolerance
+                    && v.distance(end) > tolerance
+                    && (0f32..1f32).contains(&t)
+                    && (start + edge * t).distance(v) <= tolerance;
+
+                inside.then_some((t, idx))
+            })
+            .collect();
+
+        splits.sort_by(|a, b| a.0.total_cmp(&b.0));
+        splits
+    }
+
+    /// Replaces every face with triangles, keeping the face groups
+    pub fn triangulate(&mut self, mode: Triangulation) {
+        let old_faces = std::mem::take(&mut self.faces);
+        let old_uvs = std::mem::take(&mut self.face_uvs);
+        let mut old_meta = std::mem::take(&mut self.meta);
+
+        let faces = old_faces.into_iter().zip(old_uvs);
+
+        for (face_idx, (face, uvs)) in faces.enumerate() {
+            let mut meta = old_meta.remove(&face_idx);
+            let corners: Vec<_> = (0..face.len()).collect();
+
+            let triangles: Vec<_> = match mode {
+                Triangulation::Fixed => fan(&corners[..]).collect(),
+                Triangulation::Shortest => self.clip_shortest(&face[..]),
+            };
+
+            for triangle in triangles {
+                let tri_uvs = if uvs.is_empty() {
+                    Vec::new()
+                } else {
+                    triangle.map(|corner| uvs[corner]).to_vec()
+                };
+
+                self.add_face(
+                    triangle.map(|corner| face[corner]).to_vec(),
+                    tri_uvs,
+                    meta.take(),
+                );
+            }
+        }
+    }
+
+    /// Clips ears off a polygon, always cutting the shortest diagonal that
+    /// stays inside it.  For a convex quad, this picks the shorter of its
+    /// two diagonals.  Triangles are given as corners of the face.
+    fn clip_shortest(&self, face: &[usize]) -> Vec<[usize; 3]> {
+        let normal = self.face_normal(face);
+        let mut corners: Vec<_> = (0..face.len()).collect();
+        let mut triangles = Vec::new();
+
+        while corners.len() > 3 {
+            let len = corners.len();
+            let vert = |i: usize| self.verts[face[corners[i % len]]];
+
+            // Convex corners whose triangle holds none of the other corners
+            let is_ear = |i: usize| {
+                let tri = [vert(i + len - 1), vert(i), vert(i + 1)];
+                let turns = |a: Vec3, b: Vec3, p: Vec3| {
+                    (b - a).cross(p - a).dot(normal) >= 0f32
+                };
+
+                if (tri[1] - tri[0]).cross(tri[2] - tri[1]).dot(normal)
+                    <= 0f32
+                {
+                    return false;
+                }
+
+                (0..len)
+                    .map(vert)
+                    .filter(|p| !tri.contains(p))
+                    .all(|p| {
+                        !(turns(tri[0], tri[1], p)
+                            && turns(tri[1], tri[2], p)
+                            && turns(tri[2], tri[0], p))
+                    })
+            };
+
+            let mut ears: Vec<_> = (0..len).filter(|&i| is_ear(i)).collect();
+
+            // Degenerate or self-intersecting, so cut anywhere
+            if ears.is_empty() {
+                ears = (0..len).collect();
+            }
+
+            let ear = ears.into_iter()
+                .min_by(|&a, &b| {
+                    let diagonal = |i: usize| {
+                        vert(i + len - 1).distance_squared(vert(i + 1))
+                    };
+
+                    diagonal(a).total_cmp(&diagonal(b))
+                })
+                .unwrap_or(0);
+
+            triangles.push([
+                corners[(ear + len - 1) % len],
+                corners[ear],
+                corners[(ear + 1) % len],
+            ]);
+            corners.remove(ear);
+        }
+
+        if let [a, b, c] = corners[..] {
+            triangles.push([a, b, c]);
+        }
+
+        triangles
+    }
+
+    /// Catmull-Clark subdivision, applied `levels` times.  Edges stay
+    /// sharp along open boundaries, where more than two faces meet, and
+    /// between two crease vertices.
+    pub fn subdivide(&mut self, levels: usize) {
+        for _ in 0..levels {
+            self.subdivide_once();
+        }
+    }
+
+    fn subdivide_once(&mut self) {
+        let edge_key = |a: usize, b: usize| (a.min(b), a.max(b));
+        let vert_ct = self.verts.len();
+
+        let face_points: Vec<_> = self.faces.iter()
+            .map(|face| {
+                face.iter().map(|&idx| self.verts[idx]).sum::<Vec3>()
+                    / face.len() as f32
+            })
+            .collect();
+
+        // Edges in the order faces first use them, each with its faces
+        let mut edges = Vec::<((usize, usize), Vec<usize>)>::new();
+        let mut edge_idx = HashMap::<(usize, usize), usize>::new();
+
+        for (face_idx, face) in self.faces.iter().enumerate() {
+            for corner in 0..face.len() {
+                let next = face[(corner + 1) % face.len()];
+                let key = edge_key(face[corner], next);
+                let idx = *edge_idx.entry(key).or_insert_with(|| {
+                    edges.push((key, Vec::new()));
+                    edges.len() - 1
+                });
+
+                edges[idx].1.push(face_idx);
+            }
+        }
+
+        let is_sharp = |&(a, b): &(usize, usize), faces: &[usize]| {
+            faces.len() != 2 || (self.is_crease(a) && self.is_crease(b))
+        };
+
+        let mut face_sums = vec![(Vec3::ZERO, 0usize); vert_ct];
+        let mut edge_sums = vec![(Vec3::ZERO, 0usize); vert_ct];
+        let mut sharp_neighbors = vec![Vec::<usize>::new(); vert_ct];
+
+        for (face, &point) in self.faces.iter().zip(&face_points) {
+            for &idx in face {
+                face_sums[idx].0+= point;
+                face_sums[idx].1+= 1;
+            }
+        }
+
+        for ((a, b), faces) in &edges {
+            let mid = (self.verts[*a] + self.verts[*b]) / 2f32;
+
+            for (idx, other) in [(*a, *b), (*b, *a)] {
+                edge_sums[idx].0+= mid;
+                edge_sums[idx].1+= 1;
+
+                if is_sharp(&(*a, *b), faces) {
+                    sharp_neighbors[idx].push(other);
+                }
+            }
+        }
+
+        let mut verts: Vec<_> = (0..vert_ct)
+            .map(|idx| {
+                let point = self.verts[idx];
+                let (face_sum, face_ct) = face_sums[idx];
+                let (edge_sum, edge_ct) = edge_sums[idx];
+
+                match sharp_neighbors[idx][..] {
+                    [a, b] => (self.verts[a] + point * 6f32 + self.verts[b])
+                        / 8f32,
+                    [] | [_] if face_ct > 0 && edge_ct > 0 => {
+                        let valence = edge_ct as f32;
+                        let faces = face_sum / face_ct as f32;
+                        let edges = edge_sum / edge_ct as f32;
+                        (faces + edges * 2f32 + point * (valence - 3f32))
+                            / valence
+                    },
+                    _ => point,
+                }
+            })
+            .collect();
+
+        verts.extend(&face_points);
+
+        for (key @ (a, b), faces) in &edges {
+            let ends = self.verts[*a] + self.verts[*b];
+
+            if is_sharp(key, faces) {
+                verts.push(ends / 2f32);
+            } else {
+                let centers = face_points[faces[0]] + face_points[faces[1]];
+                verts.push((ends + centers) / 4f32);
+            }
+        }
+
+        let edge_start = vert_ct + self.faces.len();
+
+        for (idx, ((a, b), _)) in edges.iter().enumerate() {
+            if self.is_crease(*a) && self.is_crease(*b) {
+                self.creases.insert(edge_start + idx);
+            }
+        }
+
+        // Texture coordinates are split linearly within each face
+        let mut uvs = self.uvs.clone();
+        let mut mid_uvs = HashMap::<(usize, usize), usize>::new();
+
+        let old_faces = std::mem::take(&mut self.faces);
+        let old_uvs = std::mem::take(&mut self.face_uvs);
+        let mut old_meta = std::mem::take(&mut self.meta);
+
+        let faces = old_faces.into_iter().zip(old_uvs);
+
+        for (face_idx, (face, face_uvs)) in faces.enumerate() {
+            let mut meta = old_meta.remove(&face_idx);
+            let len = face.len();
+            let center = vert_ct + face_idx;
+            let edge_point = |corner: usize| {
+                let next = face[(corner + 1) % len];
+                edge_start + edge_idx[&edge_key(face[corner], next)]
+            };
+
+            // Coordinates for the center and each edge's midpoint, shared
+            // with neighbors using the same coordinates on the edge
+            let mut center_uv = 0;
+            let mut edge_uvs = Vec::new();
+
+            if !face_uvs.is_empty() {
+                uvs.push(
+                    face_uvs.iter().map(|&idx| uvs[idx]).sum::<Vec2>()
+                        / len as f32
+                );
+                center_uv = uvs.len() - 1;
+
+                for corner in 0..len {
+                    let key = edge_key(
+                        face_uvs[corner],
+                        face_uvs[(corner + 1) % len],
+                    );
+                    let idx = *mid_uvs.entry(key).or_insert_with(|| {
+                        uvs.push((uvs[key.0] + uvs[key.1]) / 2f32);
+                        uvs.len() - 1
+                    });
+
+                    edge_uvs.push(idx);
+                }
+            }
+
+            for corner in 0..len {
+                let prev = (corner + len - 1) % len;
+                let quad = vec![
+                    face[corner],
+                    edge_point(corner),
+                    center,
+                    edge_point(prev),
+                ];
+
+                let quad_uvs = if face_uvs.is_empty() {
+                    Vec::new()
+                } else {
+                    vec![
+                        face_uvs[corner],
+                        edge_uvs[corner],
+                        center_uv,
+                        edge_uvs[prev],
+                    ]
+                };
+
+                self.add_face(quad, quad_uvs, meta.take());
+            }
+        }
+
+        self.verts = verts;
+        self.uvs = uvs;
+    }
+
+    /// Lays out the texture coordinate islands side by side in the unit
+    /// square, scaling them all alike.  Islands are the sets of coordinates
+    /// connected through the faces that use them.
+    pub fn pack_uvs(&mut self) {
+        let mut parents: Vec<_> = (0..self.uvs.len()).collect();
+
+        for uvs in &self.face_uvs {
+            for pair in uvs.windows(2) {
+                let root1 = find(&mut parents[..], pair[0]);
+                let root2 = find(&mut parents[..], pair[1]);
+                parents[root1] = root2;
+            }
+        }
+
+        let mut islands = HashMap::<usize, (Vec<usize>, Vec2, Vec2)>::new();
+
+        for idx in 0..self.uvs.len() {
+            let uv = self.uvs[idx];
+            let (members, mins, maxs) = islands
+                .entry(find(&mut parents[..], idx))
+                .or_insert((Vec::new(), uv, uv));
+
+            members.push(idx);
+            *mins = mins.min(uv);
+            *maxs = maxs.max(uv);
+        }
+
+        let mut islands: Vec<_> = islands.into_values().collect();
+
+        // Tallest first, filling shelves about as wide as the layout is
+        // tall
+        islands.sort_by(|a, b| {
+            (b.2.y - b.1.y).total_cmp(&(a.2.y - a.1.y))
+                .then(a.0[0].cmp(&b.0[0]))
+        });
+
+        let area: f32 = islands.iter()
+            .map(|(_, mins, maxs)| (maxs - mins).element_product())
+            .sum();
+        let widest = islands.iter()
+            .map(|(_, mins, maxs)| maxs.x - mins.x)
+            .fold(0f32, f32::max);
+        let shelf_width = area.sqrt().max(widest);
+        let padding = shelf_width * UV_PADDING;
+
+        let mut cursor = Vec2::ZERO;
+        let mut shelf_height = 0f32;
+        let mut extent = Vec2::ZERO;
+
+        for (members, mins, maxs) in &islands {
+            let size = maxs - mins;
+
+            if cursor.x > 0f32 && cursor.x + size.x > shelf_width {
+                cursor = Vec2::new(0f32, cursor.y + shelf_height + padding);
+                shelf_height = 0f32;
+            }
+
+            for &idx in members {
+                self.uvs[idx]+= cursor - mins;
+            }
+
+            extent = extent.max(cursor + size);
+            shelf_height = shelf_height.max(size.y);
+            cursor.x+= size.x + padding;
+        }
+
+        let scale = extent.max_element().max(f32::EPSILON);
+
+        for uv in &mut self.uvs {
+            *uv/= scale;
+        }
+    }
+
+    /// Copy of the mesh in other coordinates.  If they mirror the model,
+    /// faces are flipped to keep facing out.  Texture coordinates stay as
+    /// they are, so one texture fits every export.
+    pub fn transformed(&self, coords: &CoordinateSystem) -> Mesh {
+        let mut mesh = self.clone();
+
+        for v in &mut mesh.verts {
+            *v = coords.point(*v);
+        }
+
+        if coords.mirrors() {
+            for face in &mut mesh.faces {
+                face[1..].reverse();
+            }
+
+            for uvs in mesh.face_uvs.iter_mut().filter(|uvs| !uvs.is_empty()) {
+                uvs[1..].reverse();
+            }
+        }
+
+        mesh
+    }
+
+    pub fn verts(&self) -> &[Vec3] {
+        &self.verts[..]
+    }
+
+    /// Corners of the box around the vertices, if there are any
+    pub fn bounds(&self) -> Option<(Vec3, Vec3)> {
+        let first = *self.verts.first()?;
+
+        Some(self.verts.iter().fold(
+            (first, first),
+            |(mins, maxs), &v| (mins.min(v), maxs.max(v)),
+        ))
+    }
+
+    pub fn uvs(&self) -> &[Vec2] {
+        &self.uvs[..]
+    }
+
+    /// Texture coordinates of a face's corners, empty if it has none
+    pub fn face_uvs(&self, face_idx: usize) -> &[usize] {
+        &self.face_uvs[face_idx][..]
+    }
+
+    /// Polygons, each paired with the metadata of the group it belongs to
+    pub fn faces(&self) -> impl Iterator<Item = (&[usize], Option<&MeshMeta>)> {
+        let mut group = None;
+
+        self.faces.iter()
+            .enumerate()
+            .map(move |(face_idx, face)| {
+                group = self.meta.get(&face_idx).or(group);
+                (&face[..], group)
+            })
+    }
+
+    /// Group metadata if a new group starts at this face
+    pub fn group_start(&self, face_idx: usize) -> Option<&MeshMeta> {
+        self.meta.get(&face_idx)
+    }
+
+    /// Area-weighted normal of a polygon
+    pub fn face_normal(&self, face: &[usize]) -> Vec3 {
+        (0..face.len())
+            .map(|i| {
+                let v1 = self.verts[face[i]];
+                let v2 = self.verts[face[(i + 1) % face.len()]];
+                v1.cross(v2)
+            })
+            .sum::<Vec3>() / 2f32
+    }
+
+    /// Interior angle of a polygon at one of its corners
+    fn corner_angle(&self, face: &[usize], corner: usize) -> f32 {
+        let len = face.len();
+        let v = self.verts[face[corner]];
+        let prev = self.verts[face[(corner + len - 1) % len]];
+        let next = self.verts[face[(corner + 1) % len]];
+        (prev - v).angle_between(next - v)
+    }
+
+    /// Per-vertex normals, averaged from the faces sharing each vertex and
+    /// weighted by their angle there, so splitting a flat face into
+    /// triangles leaves them unchanged
+    pub fn vertex_normals(&self) -> Vec<Vec3> {
+        let mut normals = vec![Vec3::ZERO; self.verts.len()];
+
+        for (face, _) in self.faces() {
+            let normal = self.face_normal(face).normalize_or_zero();
+
+            for (corner, &idx) in face.iter().enumerate() {
+                normals[idx]+= normal * self.corner_angle(face, corner);
+            }
+        }
+
+        normals.into_iter()
+            .map(|n| n.normalize_or_zero())
+            .collect()
+    }
+
+    /// Smooth normals, weighted as in `vertex_normals`, that split along
+    /// hard edges.  An edge is hard when both of its ends are creases, so
+    /// the faces around a crease vertex are grouped by the smooth edges
+    /// they share, and each group gets its own normal.
+    pub fn corner_normals(&self) -> CornerNormals {
+        let face_normals: Vec<_> = self.faces.iter()
+            .map(|face| self.face_normal(face).normalize_or_zero())
+            .collect();
+
+        let mut incident = vec![Vec::<(usize, usize)>::new(); self.verts.len()];
+
+        for (face_idx, face) in self.faces.iter().enumerate() {
+            for (corner, &idx) in face.iter().enumerate() {
+                incident[idx].push((face_idx, corner));
+            }
+        }
+
+        let mut normals = CornerNormals {
+            normals: Vec::new(),
+            faces: self.faces.iter()
+                .map(|face| vec![0; face.len()])
+                .collect(),
+        };
+
+        for (idx, corners) in incident.iter().enumerate() {
+            let mut parents: Vec<_> = (0..corners.len()).collect();
+
+            if self.is_crease(idx) {
+                let mut by_neighbor = HashMap::<usize, usize>::new();
+
+                for (entry, &(face_idx, corner)) in corners.iter().enumerate() {
+                    let face = &self.faces[face_idx];
+                    let len = face.len();
+                    let neighbors = [
+                        face[(corner + len - 1) % len],
+                        face[(corner + 1) % len],
+                    ];
+
+                    for neighbor in neighbors {
+                        if self.is_crease(neighbor) {
+                            continue;
+                        }
+
+                        let other = *by_neighbor.entry(neighbor)
+                            .or_insert(entry);
+                        let root1 = find(&mut parents[..], entry);
+                        let root2 = find(&mut parents[..], other);
+                        parents[root1] = root2;
+                    }
+                }
+            } else {
+                parents.fill(0);
+            }
+
+            let mut groups = HashMap::<usize, usize>::new();
+
+            for (entry, &(face_idx, corner)) in corners.iter().enumerate() {
+                let root = find(&mut parents[..], entry);
+                let normal_idx = *groups.entry(root).or_insert_with(|| {
+                    normals.normals.push(Vec3::ZERO);
+                    normals.normals.len() - 1
+                });
+
+                let face = &self.faces[face_idx];
+                normals.normals[normal_idx]+= face_normals[face_idx]
+                    * self.corner_angle(face, corner);
+                normals.faces[face_idx][corner] = normal_idx;
+            }
+        }
+
+        for normal in &mut normals.normals {
+            *normal = normal.normalize_or_zero();
+        }
+
+        normals
+    }
+
+    /// Distinct segment colors referenced by the face groups
+    pub fn colors(&self) -> BTreeSet<Option<u32>> {
+        self.meta.values().map(|m| m.color).collect()
     }
 }
 
-#[derive(Clone, Debug)]
+/// Gap left between texture coordinate islands, relative to the width of
+/// the layout
+const UV_PADDING: f32 = 0.02;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Triangulation {
+    /// Fan out from each face's first vertex
+    Fixed,
+    /// Cut along the shortest diagonal available
+    Shortest,
+}
+
+impl Triangulation {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "fixed" => Some(Self::Fixed),
+            "shortest" => Some(Self::Shortest),
+            _ => None,
+        }
+    }
+}
+
+/// Splits a polygon into a fan of counter-clockwise triangles
+pub fn fan(face: &[usize]) -> impl Iterator<Item = [usize; 3]> + '_ {
+    (1..face.len().saturating_sub(1))
+        .map(|i| [face[0], face[i], face[i + 1]])
+}
+
+/// Root of the set holding `idx` in a union-find forest, halving the path
+/// on the way
+pub fn find(parents: &mut [usize], mut idx: usize) -> usize {
+    while parents[idx] != idx {
+        parents[idx] = parents[parents[idx]];
+        idx = parents[idx];
+    }
+
+    idx
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub struct MeshMeta {
     pub body_idx: usize,
     pub disk_info_idx: usize,
+    pub color: Option<u32>,
+    /// Closes off the end of a chain rather than joining two disks
+    pub cap: bool,
+    /// Chain between branch points that the loop belongs to, numbered
+    /// depth first from 0 at the root
+    pub branch: usize,
 }
 
 impl Display for MeshMeta {
@@ -317,6 +1213,183 @@ impl Display for MeshMeta {
             self.disk_info_idx,
         )?;
 
+        if self.cap {
+            write!(formatter, " cap")?;
+        }
+
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::coords::CoordinateSystem;
+    use crate::fixtures::two_ring_mesh;
+
+    use super::*;
+
+    /// Texture coordinate of every face corner, keyed by its vertex
+    fn corner_uvs(mesh: &Mesh) -> BTreeSet<(usize, usize, [u32; 2])> {
+        mesh.faces()
+            .enumerate()
+            .flat_map(|(face_idx, (face, _))| {
+                face.iter()
+                    .zip(mesh.face_uvs(face_idx))
+                    .map(move |(&idx, &uv_idx)| {
+                        let uv = mesh.uvs()[uv_idx].to_array();
+                        (face_idx, idx, uv.map(f32::to_bits))
+                    })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn mirroring_keeps_texture_coordinates() {
+        let mesh = two_ring_mesh();
+        let mirrored = mesh.transformed(&CoordinateSystem::Y_UP_SWAPPED);
+
+        assert!(CoordinateSystem::Y_UP_SWAPPED.mirrors());
+        assert_eq!(mirrored.uvs(), mesh.uvs());
+        assert_eq!(corner_uvs(&mirrored), corner_uvs(&mesh));
+
+        for ((face, _), (flipped, _)) in mesh.faces().zip(mirrored.faces()) {
+            let mut reversed = face.to_vec();
+            reversed[1..].reverse();
+            assert_eq!(flipped, reversed);
+        }
+    }
+
+    #[test]
+    fn weld_within_tolerance() {
+        // Two triangles whose shared edge was placed twice, slightly apart,
+        // and a sliver between them that welding collapses
+        let build = || {
+            let mut mesh = Mesh::default();
+            let verts = mesh.add_ring(&[
+                Vec3::ZERO,
+                Vec3::X,
+                Vec3::Y,
+                Vec3::new(1.001f32, 0f32, 0f32),
+                Vec3::new(0f32, 1.001f32, 0f32),
+                Vec3::new(1f32, 1f32, 0f32),
+                Vec3::new(0.0005f32, 0f32, 0f32),
+            ]);
+            let meta = |body_idx| Some(MeshMeta {
+                body_idx,
+                disk_info_idx: body_idx,
+                color: None,
+                cap: false,
+                branch: 0,
+            });
+
+            mesh.add_face(vec![verts[0], verts[1], verts[2]], vec![], meta(0));
+            mesh.add_face(vec![verts[0], verts[6], verts[1]], vec![], meta(1));
+            mesh.add_face(vec![verts[3], verts[5], verts[4]], vec![], None);
+            mesh
+        };
+
+        let mut loose = build();
+        loose.weld(0.0001f32);
+        assert_eq!(loose.verts().len(), 7);
+        assert_eq!(loose.faces().count(), 3);
+
+        let mut welded = build();
+        welded.weld(0.01f32);
+        let faces: Vec<_> = welded.faces().map(|(face, _)| face).collect();
+        assert_eq!(welded.verts().len(), 4);
+        assert_eq!(faces, [[0, 1, 2], [1, 3, 2]]);
+
+        // The sliver's group carries on with the face after it
+        assert_eq!(welded.group_start(1).map(|m| m.body_idx), Some(1));
+    }
+
+    #[test]
+    fn shortest_triangulation_keeps_inside() {
+        // Dart whose shortest diagonal, from the first to the third corner,
+        // runs outside it around the notch at the fourth
+        let mut mesh = Mesh::default();
+        let ring = mesh.add_ring(&[
+            Vec3::new(0f32, 0f32, 0f32),
+            Vec3::new(4f32, 0.5f32, 0f32),
+            Vec3::new(0f32, 1f32, 0f32),
+            Vec3::new(1f32, 0.5f32, 0f32),
+        ]);
+        mesh.add_face(ring, Vec::new(), None);
+
+        mesh.triangulate(Triangulation::Shortest);
+
+        assert_eq!(mesh.faces().count(), 2);
+
+        for (face, _) in mesh.faces() {
+            assert!(mesh.face_normal(face).z > 0f32, "{face:?} is flipped");
+        }
+    }
+
+    #[test]
+    fn subdivision_keeps_creases() {
+        let cube = |creased: bool| {
+            let mut mesh = Mesh::default();
+            let corners: Vec<_> = (0..8)
+                .map(|idx| Vec3::new(
+                    if idx & 1 == 0 { -1f32 } else { 1f32 },
+                    if idx & 2 == 0 { -1f32 } else { 1f32 },
+                    if idx & 4 == 0 { -1f32 } else { 1f32 },
+                ))
+                .collect();
+            let verts = mesh.add_ring(&corners);
+
+            for face in [
+                [0, 2, 3, 1], [4, 5, 7, 6], [0, 1, 5, 4],
+                [2, 6, 7, 3], [0, 4, 6, 2], [1, 3, 7, 5],
+            ] {
+                mesh.add_face(face.map(|c| verts[c]).into(), vec![], None);
+            }
+
+            // The top face's outline
+            if creased {
+                for &idx in &verts[4..] {
+                    mesh.mark_crease(idx);
+                }
+            }
+
+            mesh.subdivide(1);
+            mesh
+        };
+
+        for creased in [false, true] {
+            let mesh = cube(creased);
+
+            // Corners, then a point for each face and each edge
+            assert_eq!(mesh.verts().len(), 8 + 6 + 12);
+            assert_eq!(mesh.faces().count(), 6 * 4);
+            assert!(mesh.faces().all(|(face, _)| face.len() == 4));
+        }
+
+        // Smooth corners are pulled in towards the middle
+        let smooth = cube(false);
+        for v in &smooth.verts()[..8] {
+            assert!(v.length() < 3f32.sqrt() - 0.1f32, "{v} stayed put");
+        }
+
+        // The creased outline follows the crease rule, staying on the top
+        // face's edges, while the bottom is smoothed as before
+        let creased = cube(true);
+        let top = Vec3::new(0.75f32, 0.75f32, 1f32);
+        assert!(creased.verts()[7].abs_diff_eq(top, 1e-5));
+        assert_eq!(creased.verts()[..4], smooth.verts()[..4]);
+
+        let crease_points: Vec<_> = (0..creased.verts().len())
+            .filter(|&idx| creased.is_crease(idx))
+            .map(|idx| creased.verts()[idx])
+            .collect();
+
+        // The four corners and the midpoints of the four edges between them
+        let [corners, mids] = [0.75f32, 1f32].map(|size| {
+            crease_points.iter()
+                .filter(|v| v.z == 1f32 && v.x.abs().max(v.y.abs()) == size)
+                .count()
+        });
+        assert_eq!(crease_points.len(), 8);
+        assert_eq!((corners, mids), (4, 4));
+    }
+}
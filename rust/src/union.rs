@@ -0,0 +1,1258 @@
+use std::fmt;
+use std::collections::HashMap;
+use std::f32::consts::{E, SQRT_2};
+use std::f64::consts::TAU;
+
+use glam::{DVec3, IVec3, Vec2, Vec3, Vec3Swizzles};
+
+use fmt::{Formatter, Display};
+use crate::types::{fan, find, Mesh, MeshMeta};
+
+/// Grid cells along the longest side of the mesh
+pub const DEFAULT_RESOLUTION: usize = 64;
+
+/// Cells of padding around the mesh, so the shell closes inside the grid
+const PADDING: usize = 2;
+
+/// Tetrahedra splitting each grid cell, all sharing the diagonal from the
+/// cell's lowest corner to its highest so that neighboring cells agree on
+/// how their shared faces are cut
+const TETRAHEDRA: [[usize; 4]; 6] = [
+    [0, 5, 1, 6],
+    [0, 1, 2, 6],
+    [0, 2, 3, 6],
+    [0, 3, 7, 6],
+    [0, 7, 4, 6],
+    [0, 4, 5, 6],
+];
+
+const CORNERS: [IVec3; 8] = [
+    IVec3::new(0, 0, 0),
+    IVec3::new(1, 0, 0),
+    IVec3::new(1, 1, 0),
+    IVec3::new(0, 1, 0),
+    IVec3::new(0, 0, 1),
+    IVec3::new(1, 0, 1),
+    IVec3::new(1, 1, 1),
+    IVec3::new(0, 1, 1),
+];
+
+/// Problems met while merging the tubes
+#[derive(Clone, Debug, Default)]
+pub struct UnionReport {
+    /// Edges of the input used by a single face, through which the inside
+    /// of the mesh leaks
+    pub open_edges: usize,
+    /// Edges of the result not shared by exactly two faces
+    pub bad_edges: usize,
+    /// Pairs of input faces lying over each other within a plane
+    pub overlaps: usize,
+    /// Faces in the result
+    pub faces: usize,
+    /// Separate pieces of surface in the result, which should be one
+    pub shells: usize,
+}
+
+impl UnionReport {
+    pub fn is_clean(&self) -> bool {
+        self.open_edges == 0
+            && self.bad_edges == 0
+            && self.overlaps == 0
+            && self.shells == 1
+    }
+}
+
+impl Display for UnionReport {
+    fn fmt(&self, formatter: &mut Formatter) -> Result<(), fmt::Error> {
+        if self.open_edges > 0 {
+            writeln!(
+                formatter,
+                "union: input has {} open edges, so its inside is \
+                    ambiguous; close chain ends with --cap",
+                self.open_edges,
+            )?;
+        }
+
+        if self.bad_edges > 0 {
+            writeln!(
+                formatter,
+                "union: result has {} edges not shared by exactly \
+                 two faces",
+                self.bad_edges,
+            )?;
+        }
+
+        if self.overlaps > 0 {
+            writeln!(
+                formatter,
+                "union: {} pairs of faces lie over each other and were left \
+                 uncut",
+                self.overlaps,
+            )?;
+        }
+
+        if self.faces == 0 {
+            writeln!(
+                formatter,
+                "union: result is empty, keeping the separate tubes",
+            )?;
+        } else if self.shells > 1 {
+            writeln!(
+                formatter,
+                "union: result has {} separate shells",
+                self.shells,
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Source triangle, with the group of the face it came from
+struct Triangle<'a> {
+    verts: [Vec3; 3],
+    meta: Option<&'a MeshMeta>,
+}
+
+/// Merges everything the mesh encloses into one closed shell, rebuilt from
+/// a sampled volume with marching tetrahedra.  Points count as inside when
+/// the mesh winds around them, so overlapping tubes fuse and the result
+/// can't intersect itself.  This is not a boolean on the input faces: the
+/// whole surface is resampled, not just where tubes overlap, so detail
+/// finer than the grid cells is lost, the face count grows with the
+/// resolution, and texture coordinates, creases and vertex sources are
+/// dropped.  Each face of the shell joins the group of the nearest input
+/// face.
+pub fn voxel_union(mesh: &Mesh, resolution: usize) -> (Mesh, UnionReport) {
+    let mut report = UnionReport {
+        open_edges: edge_uses(mesh).values().filter(|&&ct| ct == 1).count(),
+        ..UnionReport::default()
+    };
+
+    let verts = mesh.verts();
+    let triangles: Vec<_> = mesh.faces()
+        .flat_map(|(face, meta)| {
+            fan(face).map(move |tri| Triangle {
+                verts: tri.map(|idx| verts[idx]),
+                meta,
+            })
+        })
+        .collect();
+
+    let Some((mins, maxs)) = mesh.bounds()
+        .filter(|_| !triangles.is_empty())
+    else {
+        return (Mesh::default(), report);
+    };
+
+    let cell_size = ((maxs - mins).max_element() / resolution.max(1) as f32)
+        .max(f32::EPSILON);
+    let origin = mins - Vec3::splat(cell_size * PADDING as f32);
+    let dims = ((maxs - mins) / cell_size).ceil().as_ivec3()
+        + IVec3::splat(2 * PADDING as i32 + 1);
+
+    let grid = Grid { origin, cell_size, dims };
+    let inside = grid.winding(&triangles[..]);
+    let (field, nearest) = grid.distance_field(&triangles[..], &inside[..]);
+
+    let mut shell = Shell::default();
+
+    for z in 0..dims.z - 1 {
+        for y in 0..dims.y - 1 {
+            for x in 0..dims.x - 1 {
+                let cell = IVec3::new(x, y, z);
+                let corners = CORNERS.map(|corner| grid.index(cell + corner));
+
+                for tet in TETRAHEDRA {
+                    shell.add_tetrahedron(
+                        &grid,
+                        tet.map(|corner| corners[corner]),
+                        &field[..],
+                    );
+                }
+            }
+        }
+    }
+
+    let result = shell.into_mesh(&grid, &triangles[..], &nearest[..]);
+
+    report.faces = result.faces().count();
+    report.bad_edges = edge_uses(&result).values()
+        .filter(|&&ct| ct != 2)
+        .count();
+    report.shells = shells(&result);
+
+    (result, report)
+}
+
+/// Merges the tubes into one shell by cutting faces along the curves where
+/// they cross each other and dropping the parts another tube encloses.
+/// Points count as enclosed when the mesh winds around them, as for
+/// [`voxel_union`].  Faces away from the crossings are kept whole, with
+/// their texture coordinates, creases and vertex sources, while faces that
+/// are cut become convex pieces of their triangles.  Faces lying over each
+/// other within a plane can't be told apart, so they are left uncut and
+/// reported.
+pub fn boolean_union(mesh: &Mesh) -> (Mesh, UnionReport) {
+    let mut report = UnionReport {
+        open_edges: edge_uses(mesh).values().filter(|&&ct| ct == 1).count(),
+        ..UnionReport::default()
+    };
+
+    let Some((mins, maxs)) = mesh.bounds() else {
+        return (Mesh::default(), report);
+    };
+
+    // Distances below these count as zero when cutting, and when welding
+    // the pieces back together
+    let size = f64::from(mins.distance(maxs)).max(f64::from(f32::EPSILON));
+    let eps = size * 1e-9;
+    let tolerance = size as f32 * 1e-5;
+
+    let faces: Vec<_> = mesh.faces().collect();
+    let mut triangles: Vec<_> = faces.iter()
+        .enumerate()
+        .flat_map(|(face_idx, (face, _))| {
+            let corners: Vec<_> = (0..face.len()).collect();
+
+            fan(&corners[..])
+                .map(|corners| {
+                    FaceTriangle::new(mesh, face_idx, face, corners)
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect();
+
+    // Every pair of triangles is tried, so models are expected to be small
+    for first in 0..triangles.len() {
+        for second in first + 1..triangles.len() {
+            let (a, b) = (&triangles[first], &triangles[second]);
+            let shared = a.indices.iter()
+                .filter(|idx| b.indices.contains(idx))
+                .count();
+
+            // Triangles of one face are taken not to cross each other
+            if a.face_idx == b.face_idx || !a.near(b, eps) {
+                continue;
+            }
+
+            match contact(a, b, eps) {
+                Contact::Apart => (),
+                Contact::Overlap => report.overlaps+= 1,
+                // Neighbors only meet along the edge they share
+                Contact::Crossing(_) if shared >= 2 => (),
+                Contact::Crossing(segment) => {
+                    triangles[first].segments.push(segment);
+                    triangles[second].segments.push(segment);
+                },
+            }
+        }
+    }
+
+    // Open ends, and rings that branches leave, are closed off for counting
+    // windings, so a point counts once for each tube around it
+    let surface: Vec<_> = triangles.iter()
+        .map(|tri| tri.verts)
+        .chain(closing_triangles(mesh))
+        .collect();
+
+    let offset = size * 1e-6;
+    let encloses = |point: DVec3| winding(&surface[..], point) > 0;
+
+    // Whether the piece faces out of the shell
+    let on_surface = |point: DVec3, normal: DVec3| {
+        encloses(point - normal * offset) && !encloses(point + normal * offset)
+    };
+
+    let mut shell = ShellBuilder::new(mesh);
+
+    for (face_idx, &(face, meta)) in faces.iter().enumerate() {
+        let face_triangles: Vec<_> = triangles.iter()
+            .filter(|tri| tri.face_idx == face_idx)
+            .collect();
+
+        let Some(first) = face_triangles.first() else {
+            continue;
+        };
+
+        if face_triangles.iter().all(|tri| tri.segments.is_empty()) {
+            if on_surface(first.centroid(), first.normal) {
+                let uvs = mesh.face_uvs(face_idx);
+                shell.add_face(face, uvs, meta);
+            }
+
+            continue;
+        }
+
+        for tri in face_triangles {
+            for piece in tri.pieces(eps) {
+                let centroid = piece.iter().map(|c| c.pos).sum::<DVec3>()
+                    / piece.len() as f64;
+
+                if on_surface(centroid, tri.normal) {
+                    shell.add_piece(tri, &piece[..], meta);
+                }
+            }
+        }
+    }
+
+    let mut result = shell.mesh;
+    result.weld(tolerance);
+    result.split_edges(tolerance);
+
+    report.faces = result.faces().count();
+    report.bad_edges = edge_uses(&result).values()
+        .filter(|&&ct| ct != 2)
+        .count();
+    report.shells = shells(&result);
+
+    (result, report)
+}
+
+/// Triangle of an input face, in double precision for cutting
+struct FaceTriangle {
+    face_idx: usize,
+    /// Corners of the face the triangle was made from
+    corners: [usize; 3],
+    indices: [usize; 3],
+    verts: [DVec3; 3],
+    /// Unit normal, zero for a degenerate triangle
+    normal: DVec3,
+    /// Where other triangles cross this one
+    segments: Vec<[DVec3; 2]>,
+}
+
+impl FaceTriangle {
+    fn new(
+        mesh: &Mesh,
+        face_idx: usize,
+        face: &[usize],
+        corners: [usize; 3],
+    ) -> Self {
+        let indices = corners.map(|corner| face[corner]);
+        let verts = indices.map(|idx| mesh.verts()[idx].as_dvec3());
+        let normal = (verts[1] - verts[0])
+            .cross(verts[2] - verts[0])
+            .normalize_or_zero();
+
+        Self { face_idx, corners, indices, verts, normal, segments: Vec::new() }
+    }
+
+    fn centroid(&self) -> DVec3 {
+        self.verts.iter().sum::<DVec3>() / 3f64
+    }
+
+    /// Whether the boxes around the triangles meet
+    fn near(&self, other: &Self, eps: f64) -> bool {
+        let bounds = |tri: &Self| {
+            let [a, b, c] = tri.verts;
+            (a.min(b).min(c), a.max(b).max(c))
+        };
+        let (mins, maxs) = bounds(self);
+        let (other_mins, other_maxs) = bounds(other);
+
+        mins.cmple(other_maxs + eps).all() && other_mins.cmple(maxs + eps).all()
+    }
+
+    /// Splits the triangle along every segment crossing it
+    fn pieces(&self, eps: f64) -> Vec<Vec<PieceCorner>> {
+        let mut pieces = vec![
+            (0..3)
+                .map(|corner| PieceCorner {
+                    pos: self.verts[corner],
+                    corner: Some(corner),
+                })
+                .collect::<Vec<_>>(),
+        ];
+
+        for &segment in &self.segments {
+            pieces = pieces.into_iter()
+                .flat_map(|piece| {
+                    match split_piece(&piece[..], segment, self.normal, eps) {
+                        Some(halves) => halves.to_vec(),
+                        None => vec![piece],
+                    }
+                })
+                .collect();
+        }
+
+        pieces
+    }
+
+    /// Barycentric coordinates of a point on the triangle
+    fn barycentric(&self, point: DVec3) -> DVec3 {
+        let [a, b, c] = self.verts;
+        let area = |p: DVec3, q: DVec3, r: DVec3| {
+            (q - p).cross(r - p).dot(self.normal)
+        };
+        let total = area(a, b, c);
+
+        if total == 0f64 {
+            return DVec3::X;
+        }
+
+        DVec3::new(area(point, b, c), area(a, point, c), area(a, b, point))
+            / total
+    }
+}
+
+/// Corner of a convex piece cut from a triangle, either one of the
+/// triangle's corners or a new point where a cut crossed its edges
+#[derive(Clone, Copy, Debug)]
+struct PieceCorner {
+    pos: DVec3,
+    corner: Option<usize>,
+}
+
+enum Contact {
+    Apart,
+    /// Triangles lying over each other within a plane
+    Overlap,
+    /// Segment along which the triangles cross
+    Crossing([DVec3; 2]),
+}
+
+/// How two triangles meet
+fn contact(a: &FaceTriangle, b: &FaceTriangle, eps: f64) -> Contact {
+    if a.normal == DVec3::ZERO || b.normal == DVec3::ZERO {
+        return Contact::Apart;
+    }
+
+    let distances = |tri: &FaceTriangle, plane: &FaceTriangle| {
+        tri.verts.map(|v| {
+            let d = plane.normal.dot(v - plane.verts[0]);
+            if d.abs() <= eps { 0f64 } else { d }
+        })
+    };
+    let (from_a, from_b) = (distances(b, a), distances(a, b));
+
+    if from_a == [0f64; 3] || from_b == [0f64; 3] {
+        return if overlap_in_plane(a, b, eps) {
+            Contact::Overlap
+        } else {
+            Contact::Apart
+        };
+    }
+
+    let direction = a.normal.cross(b.normal).normalize_or_zero();
+
+    let (Some(on_a), Some(on_b)) = (
+        plane_section(&b.verts, from_a, direction),
+        plane_section(&a.verts, from_b, direction),
+    ) else {
+        return Contact::Apart;
+    };
+
+    // Where the parts of the line inside each triangle overlap
+    let along = |v: DVec3| v.dot(direction);
+    let start = if along(on_a[0]) > along(on_b[0]) { on_a[0] } else { on_b[0] };
+    let end = if along(on_a[1]) < along(on_b[1]) { on_a[1] } else { on_b[1] };
+
+    if along(end) - along(start) <= eps {
+        return Contact::Apart;
+    }
+
+    Contact::Crossing([start, end])
+}
+
+/// Segment where a triangle meets a plane, given the distance of each
+/// vertex from it, running along `direction`
+fn plane_section(
+    verts: &[DVec3; 3],
+    distances: [f64; 3],
+    direction: DVec3,
+) -> Option<[DVec3; 2]> {
+    let mut points = Vec::new();
+
+    for idx in 0..3 {
+        let next = (idx + 1) % 3;
+        let (d, d_next) = (distances[idx], distances[next]);
+
+        if d == 0f64 {
+            points.push(verts[idx]);
+        }
+
+        if d * d_next < 0f64 {
+            points.push(verts[idx].lerp(verts[next], d / (d - d_next)));
+        }
+    }
+
+    let along = |v: &DVec3| v.dot(direction);
+    let start = *points.iter().min_by(|a, b| along(a).total_cmp(&along(b)))?;
+    let end = *points.iter().max_by(|a, b| along(a).total_cmp(&along(b)))?;
+
+    (points.len() >= 2).then_some([start, end])
+}
+
+/// Whether two triangles in the same plane cover some of the same area,
+/// looking for an edge that separates them
+fn overlap_in_plane(a: &FaceTriangle, b: &FaceTriangle, eps: f64) -> bool {
+    let edges = [a, b].into_iter().flat_map(|tri| {
+        (0..3).map(|idx| tri.verts[(idx + 1) % 3] - tri.verts[idx])
+    });
+
+    for edge in edges {
+        let axis = edge.cross(a.normal).normalize_or_zero();
+        let extent = |tri: &FaceTriangle| {
+            let along = tri.verts.map(|v| v.dot(axis));
+            (along.into_iter().fold(f64::INFINITY, f64::min),
+             along.into_iter().fold(f64::NEG_INFINITY, f64::max))
+        };
+        let ((a_min, a_max), (b_min, b_max)) = (extent(a), extent(b));
+
+        if a_max <= b_min + eps || b_max <= a_min + eps {
+            return false;
+        }
+    }
+
+    true
+}
+
+/// Splits a convex piece in two along the line through `segment`, if the
+/// segment runs through it
+fn split_piece(
+    piece: &[PieceCorner],
+    [start, end]: [DVec3; 2],
+    normal: DVec3,
+    eps: f64,
+) -> Option<[Vec<PieceCorner>; 2]> {
+    let along = (end - start).normalize_or_zero();
+    let across = along.cross(normal);
+    let sides: Vec<_> = piece.iter()
+        .map(|c| {
+            let side = across.dot(c.pos - start);
+            if side.abs() <= eps { 0f64 } else { side }
+        })
+        .collect();
+
+    if !sides.iter().any(|&side| side > 0f64)
+        || !sides.iter().any(|&side| side < 0f64)
+    {
+        return None;
+    }
+
+    let mut halves = [Vec::new(), Vec::new()];
+    let mut on_line = Vec::new();
+
+    for idx in 0..piece.len() {
+        let next = (idx + 1) % piece.len();
+        let (side, next_side) = (sides[idx], sides[next]);
+
+        if side >= 0f64 {
+            halves[0].push(piece[idx]);
+        }
+
+        if side <= 0f64 {
+            halves[1].push(piece[idx]);
+        }
+
+        if side == 0f64 {
+            on_line.push(piece[idx].pos);
+        }
+
+        if side * next_side < 0f64 {
+            let pos = piece[idx].pos.lerp(
+                piece[next].pos,
+                side / (side - next_side),
+            );
+            let corner = PieceCorner { pos, corner: None };
+
+            halves[0].push(corner);
+            halves[1].push(corner);
+            on_line.push(pos);
+        }
+    }
+
+    // The cut only matters where the segment reaches into the piece
+    let reach = on_line.iter().map(|&v| along.dot(v - start));
+    let low = reach.clone().fold(f64::INFINITY, f64::min).max(0f64);
+    let high = reach.fold(f64::NEG_INFINITY, f64::max)
+        .min(start.distance(end));
+
+    (high - low > eps).then_some(halves)
+}
+
+/// Fans closing each loop of edges that faces use more often one way
+/// than the other, such as open chain ends, or rings where branches leave
+/// a chain
+fn closing_triangles(mesh: &Mesh) -> Vec<[DVec3; 3]> {
+    // How many more times each edge is used from its lower vertex to its
+    // higher one than the other way
+    let mut balance = HashMap::<(usize, usize), i32>::new();
+
+    for (face, _) in mesh.faces() {
+        for corner in 0..face.len() {
+            let (a, b) = (face[corner], face[(corner + 1) % face.len()]);
+            *balance.entry((a.min(b), a.max(b))).or_default()+=
+                if a < b { 1 } else { -1 };
+        }
+    }
+
+    let mut leaving = HashMap::<usize, Vec<usize>>::new();
+    let mut edges: Vec<_> = balance.into_iter()
+        .flat_map(|((a, b), ct)| {
+            let edge = if ct > 0 { (a, b) } else { (b, a) };
+            std::iter::repeat_n(edge, ct.unsigned_abs() as usize)
+        })
+        .collect();
+
+    edges.sort();
+
+    for &(a, b) in edges.iter().rev() {
+        leaving.entry(a).or_default().push(b);
+    }
+
+    let verts = mesh.verts();
+    let mut triangles = Vec::new();
+
+    for (start, _) in edges {
+        let mut ring = Vec::new();
+        let mut idx = start;
+
+        while let Some(next) = leaving.get_mut(&idx).and_then(Vec::pop) {
+            ring.push(idx);
+            idx = next;
+        }
+
+        if ring.is_empty() {
+            continue;
+        }
+
+        let ring: Vec<_> = ring.into_iter()
+            .map(|idx| verts[idx].as_dvec3())
+            .collect();
+        let center = ring.iter().sum::<DVec3>() / ring.len() as f64;
+
+        // Facing against the faces around the loop
+        for corner in 0..ring.len() {
+            let next = ring[(corner + 1) % ring.len()];
+            triangles.push([center, next, ring[corner]]);
+        }
+    }
+
+    triangles
+}
+
+/// Winding number of the triangles around a point, adding up the solid
+/// angles they cover as seen from it
+fn winding(triangles: &[[DVec3; 3]], point: DVec3) -> i64 {
+    let total: f64 = triangles.iter()
+        .map(|tri| {
+            let [a, b, c] = tri.map(|v| v - point);
+            let (la, lb, lc) = (a.length(), b.length(), c.length());
+            let numerator = a.dot(b.cross(c));
+            let denominator = la * lb * lc
+                + a.dot(b) * lc
+                + b.dot(c) * la
+                + c.dot(a) * lb;
+
+            2f64 * numerator.atan2(denominator)
+        })
+        .sum();
+
+    (total / (2f64 * TAU)).round() as i64
+}
+
+/// Gathers the kept faces and pieces, taking input vertices and texture
+/// coordinates along as they are used
+struct ShellBuilder<'a> {
+    input: &'a Mesh,
+    mesh: Mesh,
+    verts: HashMap<usize, usize>,
+    uvs: HashMap<usize, usize>,
+    group: Option<MeshMeta>,
+}
+
+impl<'a> ShellBuilder<'a> {
+    fn new(input: &'a Mesh) -> Self {
+        Self {
+            input,
+            mesh: Mesh::default(),
+            verts: HashMap::new(),
+            uvs: HashMap::new(),
+            group: None,
+        }
+    }
+
+    fn vert(&mut self, idx: usize) -> usize {
+        let (input, mesh) = (self.input, &mut self.mesh);
+
+        *self.verts.entry(idx).or_insert_with(|| {
+            let new_idx = mesh.add_vert(input.verts()[idx]);
+
+            if input.is_crease(idx) {
+                mesh.mark_crease(new_idx);
+            }
+
+            if let Some(&source) = input.source(idx) {
+                mesh.set_source(new_idx, source);
+            }
+
+            new_idx
+        })
+    }
+
+    fn uv(&mut self, idx: usize) -> usize {
+        let (input, mesh) = (self.input, &mut self.mesh);
+
+        *self.uvs.entry(idx).or_insert_with(|| {
+            mesh.add_uvs(&[input.uvs()[idx]])[0]
+        })
+    }
+
+    fn add_face(
+        &mut self,
+        face: &[usize],
+        uvs: &[usize],
+        meta: Option<&MeshMeta>,
+    ) {
+        let face = face.iter().map(|&idx| self.vert(idx)).collect();
+        let uvs = uvs.iter().map(|&idx| self.uv(idx)).collect();
+        let meta = self.group(meta);
+        self.mesh.add_face(face, uvs, meta);
+    }
+
+    /// Adds part of a triangle, with new vertices where it was cut and
+    /// texture coordinates blended from the triangle's corners
+    fn add_piece(
+        &mut self,
+        tri: &FaceTriangle,
+        piece: &[PieceCorner],
+        meta: Option<&MeshMeta>,
+    ) {
+        let face_uvs = self.input.face_uvs(tri.face_idx);
+        let corner_uvs = (!face_uvs.is_empty()).then(|| {
+            tri.corners.map(|corner| self.input.uvs()[face_uvs[corner]])
+        });
+
+        let mut new_face = Vec::new();
+        let mut new_uvs = Vec::new();
+
+        for corner in piece {
+            let (idx, uv) = match corner.corner {
+                Some(tri_corner) => {
+                    let face_corner = tri.corners[tri_corner];
+                    let uv = face_uvs.get(face_corner)
+                        .map(|&uv| self.uv(uv));
+
+                    (self.vert(tri.indices[tri_corner]), uv)
+                },
+                None => {
+                    let idx = self.mesh.add_vert(corner.pos.as_vec3());
+                    let uv = corner_uvs.map(|[a, b, c]| {
+                        let weights = tri.barycentric(corner.pos).as_vec3();
+                        let uv = a * weights.x + b * weights.y + c * weights.z;
+                        self.mesh.add_uvs(&[uv])[0]
+                    });
+
+                    (idx, uv)
+                },
+            };
+
+            new_face.push(idx);
+            new_uvs.extend(uv);
+        }
+
+        let meta = self.group(meta);
+        self.mesh.add_face(new_face, new_uvs, meta);
+    }
+
+    /// Metadata to start a group with, if the face's group differs from
+    /// the last one added
+    fn group(&mut self, meta: Option<&MeshMeta>) -> Option<MeshMeta> {
+        let meta = meta.filter(|&m| self.group.as_ref() != Some(m)).cloned();
+
+        if meta.is_some() {
+            self.group = meta.clone();
+        }
+
+        meta
+    }
+}
+
+/// Number of separate pieces of surface, joined through shared vertices
+fn shells(mesh: &Mesh) -> usize {
+    let mut parents: Vec<_> = (0..mesh.verts().len()).collect();
+    let mut used = vec![false; mesh.verts().len()];
+
+    for (face, _) in mesh.faces() {
+        for &idx in face {
+            let root1 = find(&mut parents[..], face[0]);
+            let root2 = find(&mut parents[..], idx);
+            parents[root1] = root2;
+            used[idx] = true;
+        }
+    }
+
+    (0..parents.len())
+        .filter(|&idx| used[idx] && find(&mut parents[..], idx) == idx)
+        .count()
+}
+
+/// Number of faces using each undirected edge
+fn edge_uses(mesh: &Mesh) -> HashMap<(usize, usize), usize> {
+    let mut uses = HashMap::new();
+
+    for (face, _) in mesh.faces() {
+        for corner in 0..face.len() {
+            let (a, b) = (face[corner], face[(corner + 1) % face.len()]);
+            *uses.entry((a.min(b), a.max(b))).or_default()+= 1;
+        }
+    }
+
+    uses
+}
+
+struct Grid {
+    origin: Vec3,
+    cell_size: f32,
+    dims: IVec3,
+}
+
+impl Grid {
+    fn index(&self, point: IVec3) -> usize {
+        (point.x + self.dims.x * (point.y + self.dims.y * point.z)) as usize
+    }
+
+    fn position(&self, index: usize) -> Vec3 {
+        let index = index as i32;
+        let point = IVec3::new(
+            index % self.dims.x,
+            index / self.dims.x % self.dims.y,
+            index / (self.dims.x * self.dims.y),
+        );
+
+        self.origin + point.as_vec3() * self.cell_size
+    }
+
+    fn len(&self) -> usize {
+        self.dims.element_product() as usize
+    }
+
+    /// Whether each grid point lies inside the mesh, from the winding
+    /// number counted along lines of points in X
+    fn winding(&self, triangles: &[Triangle]) -> Vec<bool> {
+        // Lines are nudged off the grid so they don't graze edges shared
+        // between triangles, which would count those crossings twice
+        let nudge = self.cell_size * 1e-3 * Vec2::new(SQRT_2, E);
+        let line_ct = (self.dims.y * self.dims.z) as usize;
+        let mut lines = vec![Vec::<usize>::new(); line_ct];
+
+        for (tri_idx, tri) in triangles.iter().enumerate() {
+            let [mins, maxs] = [
+                tri.verts.iter().fold(Vec3::INFINITY, |a, &b| a.min(b)),
+                tri.verts.iter().fold(Vec3::NEG_INFINITY, |a, &b| a.max(b)),
+            ].map(|v| (v - self.origin) / self.cell_size);
+
+            let (y0, y1) = (mins.y.floor() as i32, maxs.y.ceil() as i32);
+            let (z0, z1) = (mins.z.floor() as i32, maxs.z.ceil() as i32);
+
+            for z in z0.max(0)..=z1.min(self.dims.z - 1) {
+                for y in y0.max(0)..=y1.min(self.dims.y - 1) {
+                    lines[(y + self.dims.y * z) as usize].push(tri_idx);
+                }
+            }
+        }
+
+        let mut inside = vec![false; self.len()];
+
+        for z in 0..self.dims.z {
+            for y in 0..self.dims.y {
+                let line = self.origin.yz()
+                    + Vec2::new(y as f32, z as f32) * self.cell_size
+                    + nudge;
+
+                // Crossings, entering the mesh or leaving it
+                let mut hits: Vec<(f32, i32)> = lines[
+                    (y + self.dims.y * z) as usize
+                ].iter()
+                    .filter_map(|&tri_idx| {
+                        crossing(&triangles[tri_idx].verts, line)
+                    })
+                    .collect();
+
+                hits.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+                let mut winding = 0;
+                let mut hits = hits.into_iter().peekable();
+
+                for x in 0..self.dims.x {
+                    let at = self.origin.x + x as f32 * self.cell_size;
+
+                    while let Some(&(hit, direction)) = hits.peek()
+                        && hit < at
+                    {
+                        winding+= direction;
+                        hits.next();
+                    }
+
+                    // The border stays outside, so that even a leaking
+                    // mesh gives a closed shell
+                    let point = IVec3::new(x, y, z);
+                    let border = point.min_element() == 0
+                        || (self.dims - IVec3::ONE - point).min_element() == 0;
+
+                    inside[self.index(point)] = winding > 0 && !border;
+                }
+            }
+        }
+
+        inside
+    }
+
+    /// Signed distance to the mesh, negative inside, for points near the
+    /// surface, along with the triangle closest to each.  Points further
+    /// away are clamped to a couple of cells.
+    fn distance_field(
+        &self,
+        triangles: &[Triangle],
+        inside: &[bool],
+    ) -> (Vec<f32>, Vec<Option<usize>>) {
+        let band = self.cell_size * 2f32;
+        let mut distance = vec![band; self.len()];
+        let mut nearest = vec![None; self.len()];
+
+        for (tri_idx, tri) in triangles.iter().enumerate() {
+            let [mins, maxs] = [
+                tri.verts.iter().fold(Vec3::INFINITY, |a, &b| a.min(b))
+                    - Vec3::splat(band),
+                tri.verts.iter().fold(Vec3::NEG_INFINITY, |a, &b| a.max(b))
+                    + Vec3::splat(band),
+            ].map(|v| (v - self.origin) / self.cell_size);
+
+            let lo = mins.floor().as_ivec3().max(IVec3::ZERO);
+            let hi = maxs.ceil().as_ivec3().min(self.dims - IVec3::ONE);
+
+            for z in lo.z..=hi.z {
+                for y in lo.y..=hi.y {
+                    for x in lo.x..=hi.x {
+                        let index = self.index(IVec3::new(x, y, z));
+                        let point = self.position(index);
+                        let d = closest_point(&tri.verts, point)
+                            .distance(point);
+
+                        if d < distance[index] {
+                            distance[index] = d;
+                            nearest[index] = Some(tri_idx);
+                        }
+                    }
+                }
+            }
+        }
+
+        // Points exactly on the surface count as outside, keeping every
+        // crossing strictly inside a tetrahedron's edge
+        let floor = self.cell_size * 1e-4;
+
+        let field = distance.into_iter()
+            .zip(inside)
+            .map(|(d, &inside)| {
+                if inside { -d.max(floor) } else { d.max(floor) }
+            })
+            .collect();
+
+        (field, nearest)
+    }
+}
+
+/// Where the line through `line` in Y and Z, running along X, crosses a
+/// triangle, and whether it enters the mesh there (1) or leaves it (-1)
+fn crossing(verts: &[Vec3; 3], line: Vec2) -> Option<(f32, i32)> {
+    let [a, b, c] = verts.map(|v| v.yz() - line);
+
+    // Twice the signed areas of the sub-triangles facing each vertex
+    let wa = b.perp_dot(c);
+    let wb = c.perp_dot(a);
+    let wc = a.perp_dot(b);
+
+    let all_positive = wa >= 0f32 && wb >= 0f32 && wc >= 0f32;
+    let all_negative = wa <= 0f32 && wb <= 0f32 && wc <= 0f32;
+    let total = wa + wb + wc;
+
+    if !(all_positive || all_negative) || total == 0f32 {
+        return None;
+    }
+
+    let x = (verts[0].x * wa + verts[1].x * wb + verts[2].x * wc) / total;
+
+    // A positive area means the normal points along X, out of the mesh
+    Some((x, if total > 0f32 { -1 } else { 1 }))
+}
+
+/// Closest point on a triangle
+fn closest_point(verts: &[Vec3; 3], point: Vec3) -> Vec3 {
+    let [a, b, c] = *verts;
+    let (ab, ac, ap) = (b - a, c - a, point - a);
+    let (d1, d2) = (ab.dot(ap), ac.dot(ap));
+
+    if d1 <= 0f32 && d2 <= 0f32 {
+        return a;
+    }
+
+    let bp = point - b;
+    let (d3, d4) = (ab.dot(bp), ac.dot(bp));
+
+    if d3 >= 0f32 && d4 <= d3 {
+        return b;
+    }
+
+    let vc = d1 * d4 - d3 * d2;
+
+    if vc <= 0f32 && d1 >= 0f32 && d3 <= 0f32 {
+        return a + ab * (d1 / (d1 - d3));
+    }
+
+    let cp = point - c;
+    let (d5, d6) = (ab.dot(cp), ac.dot(cp));
+
+    if d6 >= 0f32 && d5 <= d6 {
+        return c;
+    }
+
+    let vb = d5 * d2 - d1 * d6;
+
+    if vb <= 0f32 && d2 >= 0f32 && d6 <= 0f32 {
+        return a + ac * (d2 / (d2 - d6));
+    }
+
+    let va = d3 * d6 - d5 * d4;
+
+    if va <= 0f32 && d4 - d3 >= 0f32 && d5 - d6 >= 0f32 {
+        return b + (c - b) * ((d4 - d3) / ((d4 - d3) + (d5 - d6)));
+    }
+
+    let total = va + vb + vc;
+
+    if total.abs() <= f32::EPSILON {
+        return a;
+    }
+
+    a + ab * (vb / total) + ac * (vc / total)
+}
+
+/// Triangles of the rebuilt surface, with their vertices shared through
+/// the grid edges they sit on
+#[derive(Default)]
+struct Shell {
+    verts: Vec<Vec3>,
+    /// Grid points at either end of each vertex's edge
+    edges: Vec<(usize, usize)>,
+    by_edge: HashMap<(usize, usize), usize>,
+    triangles: Vec<[usize; 3]>,
+}
+
+impl Shell {
+    fn edge_vert(
+        &mut self,
+        grid: &Grid,
+        (a, b): (usize, usize),
+        field: &[f32],
+    ) -> usize {
+        let key = (a.min(b), a.max(b));
+
+        *self.by_edge.entry(key).or_insert_with(|| {
+            let t = field[a] / (field[a] - field[b]);
+            let (pos_a, pos_b) = (grid.position(a), grid.position(b));
+            self.verts.push(pos_a.lerp(pos_b, t));
+            self.edges.push(key);
+            self.verts.len() - 1
+        })
+    }
+
+    fn add_tetrahedron(&mut self, grid: &Grid, tet: [usize; 4], field: &[f32]) {
+        let (inner, outer): (Vec<_>, Vec<_>) = tet.into_iter()
+            .partition(|&idx| field[idx] < 0f32);
+
+        let polygon = match (&inner[..], &outer[..]) {
+            (&[i], &[o1, o2, o3]) => vec![(i, o1), (i, o2), (i, o3)],
+            (&[i1, i2, i3], &[o]) => vec![(i1, o), (i2, o), (i3, o)],
+            (&[i1, i2], &[o1, o2]) => {
+                vec![(i1, o1), (i1, o2), (i2, o2), (i2, o1)]
+            },
+            _ => return,
+        };
+
+        let verts: Vec<_> = polygon.into_iter()
+            .map(|edge| self.edge_vert(grid, edge, field))
+            .collect();
+
+        // Face away from the inside of the tetrahedron
+        let centroid = |points: &[usize]| {
+            points.iter().map(|&idx| grid.position(idx)).sum::<Vec3>()
+                / points.len() as f32
+        };
+        let outward = centroid(&outer[..]) - centroid(&inner[..]);
+
+        for tri in fan(&verts[..]) {
+            let [a, b, c] = tri.map(|idx| self.verts[idx]);
+
+            if (b - a).cross(c - a).dot(outward) < 0f32 {
+                self.triangles.push([tri[0], tri[2], tri[1]]);
+            } else {
+                self.triangles.push(tri);
+            }
+        }
+    }
+
+    /// Gathers the triangles into a mesh, grouped by the input faces
+    /// nearest them
+    fn into_mesh(
+        self,
+        grid: &Grid,
+        triangles: &[Triangle],
+        nearest: &[Option<usize>],
+    ) -> Mesh {
+        // Nearest input face to the grid point closest to the triangle's
+        // first vertex
+        let meta = |tri: &[usize; 3]| {
+            let (a, b) = self.edges[tri[0]];
+            let pos = self.verts[tri[0]];
+
+            [a, b].into_iter()
+                .filter_map(|point| Some((point, nearest[point]?)))
+                .min_by(|(a, _), (b, _)| {
+                    let distance = |point: usize| {
+                        grid.position(point).distance_squared(pos)
+                    };
+                    distance(*a).total_cmp(&distance(*b))
+                })
+                .and_then(|(_, tri_idx)| triangles[tri_idx].meta)
+                .cloned()
+        };
+
+        let mut grouped: Vec<_> = self.triangles.iter()
+            .map(|tri| (meta(tri), *tri))
+            .collect();
+
+        grouped.sort_by_key(|(meta, _)| {
+            meta.as_ref().map(|m| (m.body_idx, m.disk_info_idx, m.cap))
+        });
+
+        let mut mesh = Mesh::default();
+        mesh.add_ring(&self.verts[..]);
+
+        let mut group = None;
+
+        for (meta, tri) in grouped {
+            let starts_group = meta != group;
+
+            if starts_group {
+                group = meta.clone();
+            }
+
+            mesh.add_face(
+                tri.to_vec(),
+                Vec::new(),
+                meta.filter(|_| starts_group),
+            );
+        }
+
+        mesh
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Adds a box with outward faces, leaving out the top if it's `open`
+    fn add_box(mesh: &mut Mesh, mins: Vec3, maxs: Vec3, open: bool) {
+        let corners: Vec<_> = (0..8)
+            .map(|idx| Vec3::new(
+                if idx & 1 == 0 { mins.x } else { maxs.x },
+                if idx & 2 == 0 { mins.y } else { maxs.y },
+                if idx & 4 == 0 { mins.z } else { maxs.z },
+            ))
+            .collect();
+        let verts = mesh.add_ring(&corners);
+
+        for face in [
+            [0, 2, 3, 1], [0, 1, 5, 4], [2, 6, 7, 3],
+            [0, 4, 6, 2], [1, 3, 7, 5], [4, 5, 7, 6],
+        ].into_iter().take(if open { 5 } else { 6 }) {
+            mesh.add_face(face.map(|c| verts[c]).into(), vec![], None);
+        }
+    }
+
+    /// Volume enclosed by the faces
+    fn volume(mesh: &Mesh) -> f32 {
+        mesh.faces()
+            .flat_map(|(face, _)| fan(face).collect::<Vec<_>>())
+            .map(|tri| {
+                let [a, b, c] = tri.map(|idx| mesh.verts()[idx]);
+                a.dot(b.cross(c)) / 6f32
+            })
+            .sum()
+    }
+
+    #[test]
+    fn boolean_union_of_boxes() {
+        // A bar poking out through one side of a cube
+        let mut mesh = Mesh::default();
+        add_box(&mut mesh, Vec3::splat(-1f32), Vec3::ONE, false);
+        add_box(
+            &mut mesh,
+            Vec3::new(0f32, -0.5f32, -0.25f32),
+            Vec3::new(2f32, 0.5f32, 0.25f32),
+            false,
+        );
+
+        let (union, report) = boolean_union(&mesh);
+
+        assert!(report.is_clean(), "{report}");
+        assert_eq!(report.shells, 1);
+        assert!((volume(&union) - (8f32 + 0.5f32)).abs() < 1e-4);
+
+        // Nothing is left inside the other box
+        for v in union.verts() {
+            assert!(v.x >= 1f32 - 1e-5 || v.x.abs().max(v.y.abs()) <= 1f32);
+        }
+
+        // Faces the bar doesn't reach are kept whole
+        let whole = union.faces()
+            .filter(|(face, _)| {
+                face.len() == 4 && face.iter().all(|&idx| {
+                    union.verts()[idx].x == -1f32
+                })
+            })
+            .count();
+        assert_eq!(whole, 1);
+    }
+
+    #[test]
+    fn separate_shells_fail() {
+        let mut mesh = Mesh::default();
+        add_box(&mut mesh, Vec3::ZERO, Vec3::ONE, false);
+        add_box(&mut mesh, Vec3::splat(2f32), Vec3::splat(3f32), false);
+
+        for (union, report) in [boolean_union(&mesh), voxel_union(&mesh, 16)] {
+            assert_eq!(report.shells, 2);
+            assert!(!report.is_clean());
+            assert!(report.to_string().contains("2 separate shells"));
+            assert!(union.faces().count() > 0);
+        }
+    }
+
+    #[test]
+    fn open_ends_close_for_winding() {
+        let mut mesh = Mesh::default();
+        add_box(&mut mesh, Vec3::ZERO, Vec3::ONE, true);
+
+        let faces: Vec<_> = mesh.faces()
+            .flat_map(|(face, _)| fan(face).collect::<Vec<_>>())
+            .map(|tri| tri.map(|idx| mesh.verts()[idx].as_dvec3()))
+            .collect();
+        let closed: Vec<_> = faces.iter()
+            .copied()
+            .chain(closing_triangles(&mesh))
+            .collect();
+        let center = DVec3::splat(0.5f64);
+
+        // A fan over the open top
+        assert_eq!(closed.len() - faces.len(), 4);
+        assert_eq!(winding(&faces[..], center), 1);
+        assert_eq!(winding(&closed[..], center), 1);
+        assert_eq!(winding(&closed[..], center + DVec3::Z * 0.45f64), 1);
+        assert_eq!(winding(&closed[..], center + DVec3::Z), 0);
+    }
+}
@@ -0,0 +1,274 @@
+use std::fmt;
+use std::io::{self, Write};
+
+use glam::{Affine3, Mat4};
+
+use fmt::{Formatter, Display};
+use crate::types::Mesh;
+use crate::skeleton::Skeleton;
+use crate::palette::Palette;
+use crate::coords::Axis;
+use crate::export::Exporter;
+
+/// USD ASCII layer.  The body segments become nested `Xform` prims carrying
+/// their local transforms, next to a single world-space `Mesh` prim colored
+/// per face, with face-varying normals and texture coordinates, and split
+/// into one `GeomSubset` per segment loop.
+pub struct Usda<'a> {
+    pub mesh: &'a Mesh,
+    pub skeleton: &'a Skeleton,
+    pub palette: &'a Palette,
+    pub up: Axis,
+}
+
+impl Usda<'_> {
+    fn fmt_xform(
+        &self,
+        formatter: &mut Formatter,
+        bone_idx: usize,
+        depth: usize,
+    ) -> Result<(), fmt::Error> {
+        let bone = &self.skeleton.bones()[bone_idx];
+        let indent = "    ".repeat(depth);
+
+        writeln!(formatter, "{indent}def Xform \"segment_{}\"", bone.body_idx)?;
+        writeln!(formatter, "{indent}{{")?;
+        writeln!(
+            formatter,
+            "{indent}    matrix4d xformOp:transform = {}",
+            matrix(bone.local),
+        )?;
+        writeln!(
+            formatter,
+            "{indent}    uniform token[] xformOpOrder = [{:?}]",
+            "xformOp:transform",
+        )?;
+
+        for child in self.skeleton.children(bone_idx) {
+            writeln!(formatter)?;
+            self.fmt_xform(formatter, child, depth + 1)?;
+        }
+
+        writeln!(formatter, "{indent}}}")?;
+
+        Ok(())
+    }
+}
+
+impl Display for Usda<'_> {
+    fn fmt(&self, formatter: &mut Formatter) -> Result<(), fmt::Error> {
+        let mut counts = Vec::new();
+        let mut indices = Vec::new();
+        let mut colors = Vec::new();
+        let mut normals = Vec::new();
+        let mut uvs = Vec::new();
+        let corner_normals = self.mesh.corner_normals();
+        let mut subsets = Vec::<(String, Vec<usize>)>::new();
+
+        for (face_idx, (face, meta)) in self.mesh.faces().enumerate() {
+            counts.push(face.len().to_string());
+            indices.extend(face.iter().map(|idx| idx.to_string()));
+            normals.extend(
+                corner_normals.faces[face_idx].iter()
+                    .map(|&idx| {
+                        let n = corner_normals.normals[idx];
+                        vector(&n.to_array())
+                    })
+            );
+
+            let face_uvs = self.mesh.face_uvs(face_idx);
+            uvs.extend(
+                (0..face.len())
+                    .map(|corner| {
+                        let uv = face_uvs.get(corner)
+                            .map(|&idx| self.mesh.uvs()[idx])
+                            .unwrap_or_default();
+                        vector(&uv.to_array())
+                    })
+            );
+
+            let color = self.palette.color(meta.and_then(|m| m.color));
+            colors.push(vector(&color.to_array()));
+
+            if let Some(meta) = meta {
+                let name = format!(
+                    "body_{}_disk_info_{}",
+                    meta.body_idx,
+                    meta.disk_info_idx,
+                );
+
+                match subsets.iter_mut().find(|(n, _)| *n == name) {
+                    Some((_, faces)) => faces.push(face_idx),
+                    None => subsets.push((name, vec![face_idx])),
+                }
+            }
+        }
+
+        let points: Vec<_> = self.mesh.verts().iter()
+            .map(|v| vector(&v.to_array()))
+            .collect();
+
+        writeln!(formatter, "#usda 1.0")?;
+        writeln!(formatter, "(")?;
+        writeln!(formatter, "    defaultPrim = \"model\"")?;
+        writeln!(formatter, "    upAxis = \"{}\"", self.up.name())?;
+        writeln!(formatter, ")")?;
+        writeln!(formatter)?;
+        writeln!(formatter, "def Xform \"model\"")?;
+        writeln!(formatter, "{{")?;
+
+        if !self.skeleton.bones().is_empty() {
+            self.fmt_xform(formatter, 0, 1)?;
+            writeln!(formatter)?;
+        }
+
+        writeln!(formatter, "    def Mesh \"mesh\"")?;
+        writeln!(formatter, "    {{")?;
+        writeln!(
+            formatter,
+            "        int[] faceVertexCounts = [{}]",
+            counts.join(", "),
+        )?;
+        writeln!(
+            formatter,
+            "        int[] faceVertexIndices = [{}]",
+            indices.join(", "),
+        )?;
+        writeln!(
+            formatter,
+            "        point3f[] points = [{}]",
+            points.join(", "),
+        )?;
+        writeln!(
+            formatter,
+            "        normal3f[] normals = [{}] (",
+            normals.join(", "),
+        )?;
+        writeln!(formatter, "            interpolation = \"faceVarying\"")?;
+        writeln!(formatter, "        )")?;
+        writeln!(
+            formatter,
+            "        color3f[] primvars:displayColor = [{}] (",
+            colors.join(", "),
+        )?;
+        writeln!(formatter, "            interpolation = \"uniform\"")?;
+        writeln!(formatter, "        )")?;
+        writeln!(
+            formatter,
+            "        texCoord2f[] primvars:st = [{}] (",
+            uvs.join(", "),
+        )?;
+        writeln!(formatter, "            interpolation = \"faceVarying\"")?;
+        writeln!(formatter, "        )")?;
+        writeln!(
+            formatter,
+            "        uniform token subdivisionScheme = \"none\"",
+        )?;
+
+        // Every face has its loop's metadata unless the mesh was built
+        // without any
+        if !subsets.is_empty() {
+            let family_type = if self.mesh.faces().all(|(_, m)| m.is_some()) {
+                "partition"
+            } else {
+                "nonOverlapping"
+            };
+
+            writeln!(
+                formatter,
+                "        uniform token subsetFamily:segment:familyType = \
+                 \"{family_type}\"",
+            )?;
+        }
+
+        for (name, faces) in &subsets {
+            let faces: Vec<_> = faces.iter()
+                .map(|f| f.to_string())
+                .collect();
+
+            writeln!(formatter)?;
+            writeln!(formatter, "        def GeomSubset \"{name}\"")?;
+            writeln!(formatter, "        {{")?;
+            writeln!(
+                formatter,
+                "            uniform token elementType = \"face\"",
+            )?;
+            writeln!(
+                formatter,
+                "            uniform token familyName = \"segment\"",
+            )?;
+            writeln!(
+                formatter,
+                "            int[] indices = [{}]",
+                faces.join(", "),
+            )?;
+            writeln!(formatter, "        }}")?;
+        }
+
+        writeln!(formatter, "    }}")?;
+        writeln!(formatter, "}}")?;
+
+        Ok(())
+    }
+}
+
+impl Exporter for Usda<'_> {
+    fn export(&self, writer: &mut dyn Write) -> io::Result<()> {
+        write!(writer, "{self}")
+    }
+}
+
+/// USD matrices are row-major with the translation in the last row, which
+/// lines up with glam's columns
+fn matrix(xform: Affine3) -> String {
+    let rows: Vec<_> = Mat4::from(xform).to_cols_array_2d().iter()
+        .map(|row| vector(row))
+        .collect();
+
+    format!("( {} )", rows.join(", "))
+}
+
+/// Tuple of components, each as `number` writes it
+fn vector(components: &[f32]) -> String {
+    let components: Vec<_> = components.iter()
+        .map(|&v| number(v))
+        .collect();
+
+    format!("({})", components.join(", "))
+}
+
+/// Six decimal places, without a sign on values that round to zero so the
+/// output doesn't change with rounding noise
+fn number(value: f32) -> String {
+    let text = format!("{value:.6}");
+
+    match text.strip_prefix('-') {
+        Some(unsigned) if unsigned.bytes().all(|b| b == b'0' || b == b'.') => {
+            unsigned.to_string()
+        },
+        _ => text,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fixtures::{two_ring_mesh, two_segment_body};
+    use crate::select::Selection;
+
+    use super::*;
+
+    #[test]
+    fn snapshot() {
+        let mesh = two_ring_mesh();
+        let body = two_segment_body();
+        let skeleton = Skeleton::from_body(Some(&body), &Selection::default());
+        let usda = Usda {
+            mesh: &mesh,
+            skeleton: &skeleton,
+            palette: &Palette::default(),
+            up: Axis::Z,
+        };
+
+        assert_eq!(usda.to_string(), include_str!("usd/two_rings.usda"));
+    }
+}
@@ -0,0 +1,98 @@
+use std::io::{self, Write};
+use std::path::Path;
+
+use crate::types::Mesh;
+use crate::export::Exporter;
+
+const FIELDS: [&str; 6] = [
+    "vertex", "body", "disk_info", "disk", "disk_vertex", "attribute",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum VertexMapFormat {
+    Json,
+    Csv,
+}
+
+impl VertexMapFormat {
+    /// CSV for paths ending in `.csv`, otherwise JSON
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("csv") => Self::Csv,
+            _ => Self::Json,
+        }
+    }
+}
+
+/// Sidecar listing, for every output vertex in index order, the body
+/// segment, disk information entry, disk and disk vertex it was placed
+/// from.  Vertices made while building, such as in caps, interpolated
+/// rings and subdivision, have no source and leave those fields empty.
+pub struct VertexMap<'a> {
+    pub mesh: &'a Mesh,
+    /// Mesh vertex behind each output vertex, as the exporter orders them
+    pub order: &'a [usize],
+    pub format: VertexMapFormat,
+}
+
+impl VertexMap<'_> {
+    fn rows(&self) -> impl Iterator<Item = [Option<String>; 6]> + '_ {
+        self.order.iter()
+            .enumerate()
+            .map(|(idx, &vert)| {
+                let source = self.mesh.source(vert);
+                let field = |value: fn(_) -> String| source.map(value);
+
+                [
+                    Some(idx.to_string()),
+                    field(|s| s.body_idx.to_string()),
+                    field(|s| s.disk_info_idx.to_string()),
+                    field(|s| s.disk_idx.to_string()),
+                    field(|s| s.vertex_idx.to_string()),
+                    field(|s| s.attr.to_string()),
+                ]
+            })
+    }
+}
+
+impl Exporter for VertexMap<'_> {
+    fn export(&self, writer: &mut dyn Write) -> io::Result<()> {
+        match self.format {
+            VertexMapFormat::Csv => {
+                writeln!(writer, "{}", FIELDS.join(","))?;
+
+                for row in self.rows() {
+                    let row: Vec<_> = row.into_iter()
+                        .map(Option::unwrap_or_default)
+                        .collect();
+                    writeln!(writer, "{}", row.join(","))?;
+                }
+            },
+            VertexMapFormat::Json => {
+                writeln!(writer, "[")?;
+
+                for (idx, row) in self.rows().enumerate() {
+                    let fields: Vec<_> = FIELDS.iter()
+                        .zip(row)
+                        .map(|(name, value)| {
+                            let value = value.unwrap_or("null".into());
+                            format!("\"{name}\": {value}")
+                        })
+                        .collect();
+
+                    let comma = if idx + 1 < self.order.len() {
+                        ","
+                    } else {
+                        ""
+                    };
+
+                    writeln!(writer, "  {{{}}}{comma}", fields.join(", "))?;
+                }
+
+                writeln!(writer, "]")?;
+            },
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,51 @@
+use glam::{Vec2, Vec3};
+
+/// Orthographic direction to draw the model from, which faces along Y
+/// with Z up
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum View {
+    /// From in front, looking back along Y
+    Front,
+    /// From the model's right, with its front to the right
+    #[default]
+    Side,
+    /// From above, with its front at the top
+    Top,
+}
+
+impl View {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "front" => Some(Self::Front),
+            "side" => Some(Self::Side),
+            "top" => Some(Self::Top),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Front => "front",
+            Self::Side => "side",
+            Self::Top => "top",
+        }
+    }
+
+    /// Position in the view, with X to the right and Y up
+    pub fn project(&self, v: Vec3) -> Vec2 {
+        match self {
+            Self::Front => Vec2::new(-v.x, v.z),
+            Self::Side => Vec2::new(v.y, v.z),
+            Self::Top => Vec2::new(v.x, v.y),
+        }
+    }
+
+    /// Direction from the model towards the viewer
+    pub fn toward_viewer(&self) -> Vec3 {
+        match self {
+            Self::Front => Vec3::Y,
+            Self::Side => Vec3::X,
+            Self::Top => Vec3::Z,
+        }
+    }
+}